@@ -0,0 +1,270 @@
+use std::fmt;
+
+use crate::arpx_job::process::{bare_name, is_name_continue, is_name_start};
+use crate::generic::builtin_matchers::string::{quote, quoted_string};
+use crate::generic::combinators::parser::{Expected, ParseFailure};
+use crate::prelude::*;
+
+/// A `key=value` argument passed to a process, as in
+/// `deploy(env="staging", replicas=3);`. An argument written as just `key`
+/// is short for `key=key`, passing on the variable or macro parameter of the
+/// same name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Arg {
+    pub name: Spanned<String>,
+    pub value: Spanned<Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Number(Number),
+    Boolean(bool),
+    /// A bare name such as `target`, referring to a parameter of the macro
+    /// the argument is in or to a variable. `parse_job` replaces each with
+    /// the value it refers to.
+    Reference(String),
+}
+
+/// A decimal number, kept as written so that it converts losslessly to
+/// whichever numeric type the runtime needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Number(String);
+
+impl Number {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    #[must_use]
+    pub fn as_i64(&self) -> Option<i64> {
+        self.0.parse().ok()
+    }
+
+    #[must_use]
+    pub fn as_f64(&self) -> Option<f64> {
+        self.0.parse().ok()
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Writes the value the way a job script would spell it.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(value) => f.write_str(&quote(value)),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Reference(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Parses a parenthesized, comma-separated argument list. A trailing comma
+/// is allowed, and each argument name may only be given once.
+pub fn args<'a>() -> impl Parser<'a, Vec<Arg>> {
+    let list = pair(arg(), n(right(whitespace_wrap(literal(",")), arg()), 0..))
+        .map(|(first, rest)| std::iter::once(first).chain(rest).collect());
+
+    right(
+        whitespace_wrap(literal("(")),
+        left(
+            optional(left(list, optional(whitespace_wrap(literal(","))))),
+            whitespace_wrap(literal(")")),
+        ),
+    )
+    .map(Option::unwrap_or_default)
+    .validate(|args: &Vec<Arg>| {
+        reject_duplicates(
+            args,
+            |arg| Spanned::new(arg.name.value.as_str(), arg.name.span),
+            |name| format!("`{}` is already given", name),
+        )
+    })
+}
+
+fn arg<'a>() -> impl Parser<'a, Arg> {
+    pair(
+        whitespace_wrap(bare_name("argument name").spanned()),
+        optional(right(whitespace_wrap(literal("=")), value.spanned())),
+    )
+    .map(|(name, value)| Arg {
+        value: value
+            .unwrap_or_else(|| Spanned::new(Value::Reference(name.value.clone()), name.span)),
+        name,
+    })
+}
+
+/// Parses a quoted string, a decimal number such as `3`, `-1` or `0.5`,
+/// `true` or `false`, or a bare name referring to a parameter or variable.
+fn value(input: Input<'_>) -> ParseResult<'_, Value> {
+    let text = input.as_str();
+    let word_len = text
+        .find(|next: char| !is_name_continue(next))
+        .unwrap_or(text.len());
+
+    match &text[..word_len] {
+        _ if text.starts_with('"') => Ok(quoted_string(input)?.map(Value::String)),
+        "true" => Ok(Parsed::new(input.advance(4), Value::Boolean(true))),
+        "false" => Ok(Parsed::new(input.advance(5), Value::Boolean(false))),
+        word if text.starts_with(is_name_start) => Ok(Parsed::new(
+            input.advance(word_len),
+            Value::Reference(word.to_string()),
+        )),
+        _ => match number_len(text) {
+            Some(len) => Ok(Parsed::new(
+                input.advance(len),
+                Value::Number(Number(text[..len].to_string())),
+            )),
+            None => Err(ParseFailure::new(
+                text,
+                vec![Expected::Label("argument value")],
+            )),
+        },
+    }
+}
+
+/// Returns the length of the number at the start of `text`: an optional
+/// `-`, digits, and optionally a `.` followed by more digits.
+fn number_len(text: &str) -> Option<usize> {
+    let digits = |from: usize| {
+        text[from..]
+            .find(|next: char| !next.is_ascii_digit())
+            .unwrap_or(text.len() - from)
+    };
+
+    let sign = usize::from(text.starts_with('-'));
+    let whole = digits(sign);
+
+    if whole == 0 {
+        return None;
+    }
+
+    let end = sign + whole;
+
+    match text[end..].strip_prefix('.') {
+        Some(_) if digits(end + 1) > 0 => Some(end + 1 + digits(end + 1)),
+        _ => Some(end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::span::spanned;
+
+    fn number(value: &str) -> Value {
+        Value::Number(Number(value.to_string()))
+    }
+
+    #[test]
+    fn test_args() -> Result<(), String> {
+        let example = r#"(env="staging", replicas=3, dry_run = false)"#;
+
+        let expected = vec![
+            Arg {
+                name: spanned("env", 1),
+                value: Spanned::new(Value::String("staging".to_string()), Span::new(5, 14)),
+            },
+            Arg {
+                name: spanned("replicas", 16),
+                value: Spanned::new(number("3"), Span::new(25, 26)),
+            },
+            Arg {
+                name: spanned("dry_run", 28),
+                value: Spanned::new(Value::Boolean(false), Span::new(38, 43)),
+            },
+        ];
+
+        assert_eq!(args().parse(example)?, ("", expected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_args_and_trailing_comma() -> Result<(), String> {
+        assert_eq!(args().parse("()")?, ("", Vec::new()));
+        assert_eq!(args().parse("( )")?, ("", Vec::new()));
+        assert_eq!(args().parse("(a=1,)")?.output.len(), 1);
+        assert_eq!(
+            args().parse("(,)"),
+            Err(ParseFailure::new(
+                ",)",
+                vec![Expected::Label("argument name"), Expected::Literal(")")]
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_shorthand_args() -> Result<(), String> {
+        assert_eq!(
+            args().parse("(target, env=stage)")?.output,
+            vec![
+                Arg {
+                    name: spanned("target", 1),
+                    value: Spanned::new(Value::Reference("target".to_string()), Span::new(1, 7)),
+                },
+                Arg {
+                    name: spanned("env", 9),
+                    value: Spanned::new(Value::Reference("stage".to_string()), Span::new(13, 18)),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_values() -> Result<(), String> {
+        assert_eq!(value.parse("true)")?, (")", Value::Boolean(true)));
+        assert_eq!(value.parse("-12.5,")?, (",", number("-12.5")));
+        assert_eq!(value.parse("7.)")?, (".)", number("7")));
+        assert_eq!(
+            value.parse(r#""a\"b""#)?,
+            ("", Value::String("a\"b".to_string()))
+        );
+        assert_eq!(
+            value.parse("truthy)")?,
+            (")", Value::Reference("truthy".to_string()))
+        );
+        assert_eq!(
+            value.parse("-"),
+            Err(ParseFailure::new(
+                "-",
+                vec![Expected::Label("argument value")]
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_conversions() {
+        assert_eq!(Number("3".to_string()).as_i64(), Some(3));
+        assert_eq!(Number("0.5".to_string()).as_i64(), None);
+        assert_eq!(Number("0.5".to_string()).as_f64(), Some(0.5));
+    }
+
+    #[test]
+    fn test_duplicate_args() {
+        assert_eq!(
+            args().parse("(env=1, env=2)"),
+            Err(ParseFailure::rejected(
+                "",
+                Rejection::new(Span::new(8, 11), "`env` is already given")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_value_display_round_trips() -> Result<(), String> {
+        for example in [r#""say \"hi\"""#, "-0.25", "true", "target"] {
+            assert_eq!(value.parse(example)?.output.to_string(), example);
+        }
+        Ok(())
+    }
+}
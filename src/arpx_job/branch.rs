@@ -0,0 +1,177 @@
+use crate::arpx_job::handler::{handler, Handler};
+use crate::generic::builtin_matchers::integer::unsigned;
+use crate::prelude::*;
+
+/// A handler that runs only for particular exit codes, as in `?2 skip` or
+/// `?3..9 partial`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Branch {
+    pub codes: Spanned<ExitCodes>,
+    pub target: Handler,
+}
+
+/// The exit codes a branch matches. Ranges include both ends, so `3..9`
+/// matches 3 and 9.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCodes {
+    Code(i32),
+    Range(i32, i32),
+}
+
+impl ExitCodes {
+    #[must_use]
+    pub fn contains(&self, code: i32) -> bool {
+        match *self {
+            ExitCodes::Code(expected) => code == expected,
+            ExitCodes::Range(start, end) => (start..=end).contains(&code),
+        }
+    }
+
+    fn bounds(&self) -> (i32, i32) {
+        match *self {
+            ExitCodes::Code(code) => (code, code),
+            ExitCodes::Range(start, end) => (start, end),
+        }
+    }
+}
+
+/// Parses any number of exit code branches. Branches are tried in order, so
+/// one may narrow a later one, but a branch whose codes are all taken by
+/// earlier branches is rejected.
+pub fn branches<'a>() -> impl Parser<'a, Vec<Branch>> {
+    let branch = pair(
+        right(whitespace_wrap(literal("?")), exit_codes().spanned()),
+        whitespace_wrap(handler()),
+    )
+    .map(|(codes, target)| Branch { codes, target });
+
+    n(branch, 0..).validate(|branches| {
+        for (index, branch) in branches.iter().enumerate() {
+            if is_covered(branch.codes.value, &branches[..index]) {
+                return Err(Rejection::new(
+                    branch.codes.span,
+                    "this branch never runs because earlier branches take all of its exit codes",
+                ));
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn exit_codes<'a>() -> impl Parser<'a, ExitCodes> {
+    pair(exit_code(), optional(right(literal(".."), exit_code())))
+        .spanned()
+        .validate(|codes| match codes.value {
+            (start, Some(end)) if start > end => Err(Rejection::new(
+                codes.span,
+                "this range of exit codes is empty",
+            )
+            .with_note(format!(
+                "write the lower exit code first, as in `{}..{}`",
+                end, start
+            ))),
+            _ => Ok(()),
+        })
+        .map(|codes| match codes.value {
+            (code, None) => ExitCodes::Code(code),
+            (start, Some(end)) => ExitCodes::Range(start, end),
+        })
+}
+
+fn exit_code<'a>() -> impl Parser<'a, i32> {
+    unsigned("exit code")
+}
+
+/// Returns whether every code in `codes` is matched by one of `earlier`.
+fn is_covered(codes: ExitCodes, earlier: &[Branch]) -> bool {
+    let (start, end) = codes.bounds();
+    let mut bounds: Vec<(i32, i32)> = earlier
+        .iter()
+        .map(|branch| branch.codes.value.bounds())
+        .collect();
+    bounds.sort_unstable();
+
+    let mut next = start;
+
+    for (earlier_start, earlier_end) in bounds {
+        if earlier_start <= next && next <= earlier_end {
+            match earlier_end.checked_add(1) {
+                Some(after) => next = after,
+                None => return true,
+            }
+        }
+    }
+
+    next > end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
+    use crate::generic::span::spanned;
+
+    #[test]
+    fn test_branches() -> Result<(), String> {
+        let example = "?0 next ?2 skip ?3..9 partial : fail";
+
+        let expected = vec![
+            Branch {
+                codes: Spanned::new(ExitCodes::Code(0), Span::new(1, 2)),
+                target: Handler::Process(spanned("next", 3), Vec::new()),
+            },
+            Branch {
+                codes: Spanned::new(ExitCodes::Code(2), Span::new(9, 10)),
+                target: Handler::Process(spanned("skip", 11), Vec::new()),
+            },
+            Branch {
+                codes: Spanned::new(ExitCodes::Range(3, 9), Span::new(17, 21)),
+                target: Handler::Process(spanned("partial", 22), Vec::new()),
+            },
+        ];
+
+        assert_eq!(branches().parse(example)?, (": fail", expected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_codes_contains() {
+        assert!(ExitCodes::Code(2).contains(2));
+        assert!(!ExitCodes::Code(2).contains(3));
+        assert!(ExitCodes::Range(3, 9).contains(3));
+        assert!(ExitCodes::Range(3, 9).contains(9));
+        assert!(!ExitCodes::Range(3, 9).contains(10));
+    }
+
+    #[test]
+    fn test_empty_range_of_exit_codes() {
+        assert_eq!(
+            exit_codes().parse("9..3 partial"),
+            Err(ParseFailure::rejected(
+                " partial",
+                Rejection::new(Span::new(0, 4), "this range of exit codes is empty")
+                    .with_note("write the lower exit code first, as in `3..9`")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_branch_covered_by_earlier_branches() -> Result<(), String> {
+        assert_eq!(branches().parse("?2 a ?0..9 b")?.output.len(), 2);
+        assert_eq!(
+            branches().parse("?0..4 a ?5..9 b ?3..7 c;"),
+            Err(ParseFailure {
+                expected: vec![Expected::Literal("("), Expected::Literal("?")],
+                ..ParseFailure::rejected(
+                    ";",
+                    Rejection::new(
+                        Span::new(17, 21),
+                        "this branch never runs because earlier branches take all of its exit codes"
+                    )
+                )
+            })
+        );
+        Ok(())
+    }
+}
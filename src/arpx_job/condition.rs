@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::arpx_job::job::MAX_NESTING;
+use crate::arpx_job::process::bare_name;
+use crate::arpx_job::task::{sequential, Task};
+use crate::generic::builtin_matchers::literal::keyword;
+use crate::generic::builtin_matchers::string::quoted_string;
+use crate::generic::combinators::parser::{BoxedParser, ParseFailure};
+use crate::prelude::*;
+
+/// A boolean expression that decides whether a task runs, as in
+/// `env.CI == "true" && !(branch == "main" || branch == "release")`. `!`
+/// binds tightest, then `&&`, then `||`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Equal(Operand, Operand),
+    NotEqual(Operand, Operand),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// A variable such as `branch` or `env.CI`, looked up when the condition
+    /// is evaluated. `parse_job` replaces a variable declared with an earlier
+    /// `let` by a string holding its value.
+    Variable(Spanned<String>),
+    String(Spanned<String>),
+}
+
+/// A block that only runs if a condition holds:
+/// `when env.CI == "true" { upload; }`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct When {
+    pub condition: Spanned<Condition>,
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+/// A condition refers to a variable that is not in the map it is evaluated
+/// against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvaluationError {
+    pub variable: Spanned<String>,
+}
+
+impl fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not defined", self.variable.value)
+    }
+}
+
+impl Error for EvaluationError {}
+
+impl Condition {
+    /// Evaluates the condition with the values of its variables taken from
+    /// `vars`, keyed by their full names such as `env.CI`. `&&` and `||`
+    /// short-circuit, so a variable is only required if its value matters.
+    pub fn evaluate(&self, vars: &HashMap<String, String>) -> Result<bool, EvaluationError> {
+        match self {
+            Condition::Equal(left, right) => Ok(left.evaluate(vars)? == right.evaluate(vars)?),
+            Condition::NotEqual(left, right) => Ok(left.evaluate(vars)? != right.evaluate(vars)?),
+            Condition::And(left, right) => Ok(left.evaluate(vars)? && right.evaluate(vars)?),
+            Condition::Or(left, right) => Ok(left.evaluate(vars)? || right.evaluate(vars)?),
+            Condition::Not(condition) => Ok(!condition.evaluate(vars)?),
+        }
+    }
+}
+
+impl Operand {
+    fn evaluate<'v>(
+        &'v self,
+        vars: &'v HashMap<String, String>,
+    ) -> Result<&'v str, EvaluationError> {
+        match self {
+            Operand::Variable(name) => {
+                vars.get(&name.value)
+                    .map(String::as_str)
+                    .ok_or_else(|| EvaluationError {
+                        variable: name.clone(),
+                    })
+            }
+            Operand::String(value) => Ok(&value.value),
+        }
+    }
+}
+
+pub fn when<'a>() -> impl Parser<'a, When> {
+    whitespace_wrap(
+        pair(right(keyword("when"), condition().spanned()), sequential())
+            .spanned()
+            .map(|Spanned { value, span }| {
+                let (condition, body) = value;
+
+                When {
+                    condition,
+                    span: Span::new(span.start, body.span.end),
+                    tasks: body.tasks,
+                }
+            }),
+    )
+}
+
+/// Parses the `when ...;` that may follow a process's `;`. It ends in a `;`
+/// of its own, so that it cannot be mistaken for a conditional block, except
+/// at the end of the script, as in `deploy; when branch == "main"`.
+pub(crate) fn when_clause<'a>() -> impl Parser<'a, Spanned<Condition>> {
+    left(
+        right(keyword("when"), condition().spanned()),
+        either(token(";"), end_of_script),
+    )
+}
+
+/// Succeeds at the end of the input, past any whitespace and comments.
+fn end_of_script(input: Input<'_>) -> ParseResult<'_, ()> {
+    let parsed = space0().parse_input(input)?;
+
+    if parsed.remaining.as_str().is_empty() {
+        Ok(Parsed::new(input, ()))
+    } else {
+        Err(ParseFailure::new(parsed.remaining.as_str(), Vec::new()))
+    }
+}
+
+/// Parses a condition. Whitespace is skipped before each token but not after
+/// the last, so that a spanned condition ends where its last token does.
+pub fn condition<'a>() -> impl Parser<'a, Condition> {
+    disjunction(0)
+}
+
+fn token<'a>(text: &'static str) -> impl Parser<'a, ()> {
+    right(space0(), literal(text))
+}
+
+/// Parses conditions joined by `||`, inside `depth` parentheses.
+fn disjunction<'a>(depth: usize) -> BoxedParser<'a, Condition> {
+    BoxedParser::new(
+        pair(
+            conjunction(depth),
+            n(right(token("||"), conjunction(depth)), 0..),
+        )
+        .map(|(first, rest)| {
+            rest.into_iter().fold(first, |left, right| {
+                Condition::Or(Box::new(left), Box::new(right))
+            })
+        }),
+    )
+}
+
+fn conjunction<'a>(depth: usize) -> impl Parser<'a, Condition> {
+    pair(negation(depth), n(right(token("&&"), negation(depth)), 0..)).map(|(first, rest)| {
+        rest.into_iter().fold(first, |left, right| {
+            Condition::And(Box::new(left), Box::new(right))
+        })
+    })
+}
+
+fn negation<'a>(depth: usize) -> impl Parser<'a, Condition> {
+    pair(n(token("!"), 0..), primary(depth)).map(|(nots, condition)| {
+        nots.into_iter().fold(condition, |condition, ()| {
+            Condition::Not(Box::new(condition))
+        })
+    })
+}
+
+fn primary<'a>(depth: usize) -> impl Parser<'a, Condition> {
+    let parenthesized = move |input: Input<'a>| {
+        let open = right(space0(), literal("(").spanned()).parse_input(input)?;
+
+        if depth == MAX_NESTING {
+            return Err(ParseFailure::rejected(
+                input.as_str(),
+                Rejection::new(
+                    open.output.span,
+                    format!(
+                        "conditions cannot nest more than {} levels deep",
+                        MAX_NESTING
+                    ),
+                ),
+            ));
+        }
+
+        left(disjunction(depth + 1), token(")")).parse_input(open.remaining)
+    };
+
+    let comparison = pair(
+        operand(),
+        pair(
+            either(token("==").map(|()| true), token("!=").map(|()| false)),
+            operand(),
+        ),
+    )
+    .map(|(left, (equal, right))| {
+        if equal {
+            Condition::Equal(left, right)
+        } else {
+            Condition::NotEqual(left, right)
+        }
+    });
+
+    either(parenthesized, comparison)
+}
+
+fn operand<'a>() -> impl Parser<'a, Operand> {
+    right(
+        space0(),
+        either(
+            quoted_string.spanned().map(Operand::String),
+            variable_path.spanned().map(Operand::Variable),
+        ),
+    )
+}
+
+/// Parses a variable name made of bare names joined by `.`, as in `env.CI`.
+fn variable_path(input: Input<'_>) -> ParseResult<'_, String> {
+    pair(
+        bare_name("variable"),
+        n(right(past_trivia(literal(".")), bare_name("variable")), 0..),
+    )
+    .map(|(first, rest)| {
+        std::iter::once(first)
+            .chain(rest)
+            .collect::<Vec<_>>()
+            .join(".")
+    })
+    .parse_input(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::Expected;
+    use crate::generic::span::spanned;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_condition() -> Result<(), String> {
+        assert_eq!(
+            condition().parse(r#"env.CI == "true" { upload; }"#)?,
+            (
+                " { upload; }",
+                Condition::Equal(
+                    Operand::Variable(spanned("env.CI", 0)),
+                    Operand::String(Spanned::new("true".to_string(), Span::new(10, 16))),
+                )
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_precedence() -> Result<(), String> {
+        let parsed = condition().parse(r#"!a == "1" || b != "2" && (c == "3")"#)?;
+        let compare = |equal: bool, name: &str, start: usize, value: &str| {
+            let left = Operand::Variable(spanned(name, start));
+            let right = Operand::String(Spanned::new(
+                value.to_string(),
+                Span::new(start + 5, start + 8),
+            ));
+
+            if equal {
+                Condition::Equal(left, right)
+            } else {
+                Condition::NotEqual(left, right)
+            }
+        };
+
+        assert_eq!(
+            parsed.output,
+            Condition::Or(
+                Box::new(Condition::Not(Box::new(compare(true, "a", 1, "1")))),
+                Box::new(Condition::And(
+                    Box::new(compare(false, "b", 13, "2")),
+                    Box::new(compare(true, "c", 26, "3")),
+                )),
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate() -> Result<(), String> {
+        let condition = condition()
+            .parse(r#"env.CI == "true" && !(branch == "main" || branch == "release")"#)?
+            .output;
+
+        assert_eq!(
+            condition.evaluate(&vars(&[("env.CI", "true"), ("branch", "dev")])),
+            Ok(true)
+        );
+        assert_eq!(
+            condition.evaluate(&vars(&[("env.CI", "true"), ("branch", "main")])),
+            Ok(false)
+        );
+        assert_eq!(condition.evaluate(&vars(&[("env.CI", "false")])), Ok(false));
+        assert_eq!(
+            condition
+                .evaluate(&vars(&[("env.CI", "true")]))
+                .map_err(|error| error.to_string()),
+            Err("`branch` is not defined".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_when() -> Result<(), String> {
+        let parsed = when().parse(r#"when branch != "main" { a; b; } c;"#)?;
+
+        assert_eq!(parsed.remaining.as_str(), "c;");
+        assert_eq!(parsed.output.condition.span, Span::new(5, 21));
+        assert_eq!(parsed.output.tasks.len(), 2);
+        assert_eq!(parsed.output.span, Span::new(0, 31));
+        Ok(())
+    }
+
+    #[test]
+    fn test_when_clause() -> Result<(), String> {
+        let parsed = when_clause().parse(r#"when a == "b" ; c;"#)?;
+
+        assert_eq!(parsed.remaining.as_str(), " c;");
+        assert_eq!(parsed.output.span, Span::new(5, 13));
+        assert!(when_clause().parse(r#"when a == "b" c;"#).is_err());
+        assert_eq!(
+            when_clause()
+                .parse(r#"when a == "b" # done"#)?
+                .remaining
+                .as_str(),
+            " # done"
+        );
+        assert!(when_clause().parse(r#"when a == "b" { c; }"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_conditions() {
+        assert_eq!(
+            condition()
+                .parse("a = b")
+                .map_err(|failure| failure.expected),
+            Err(vec![
+                Expected::Literal("."),
+                Expected::Literal("=="),
+                Expected::Literal("!=")
+            ])
+        );
+
+        let nested = format!("{}a == b{}", "(".repeat(40), ")".repeat(40));
+
+        assert_eq!(
+            condition()
+                .parse(&nested)
+                .map_err(|failure| failure.rejection),
+            Err(Some(Spanned::new(
+                "conditions cannot nest more than 32 levels deep".to_string(),
+                Span::new(32, 33),
+            )))
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::arpx_job::args::{args, Arg};
+use crate::arpx_job::process::process_name;
+use crate::arpx_job::settings::Concurrency;
+use crate::arpx_job::task::{concurrent, sequential, Group};
+use crate::prelude::*;
+
+/// What runs when a process succeeds, fails or times out: another process
+/// by name, with any arguments, or a whole block of tasks, as in
+/// `build ? [ test; lint; ] : { notify(channel="ops"); cleanup; };`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Handler {
+    /// A process to run, whose span is that of its name.
+    Process(Spanned<String>, Vec<Arg>),
+    Concurrent(Group, Concurrency),
+    Sequential(Group),
+}
+
+impl Handler {
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Handler::Process(name, _) => name.span,
+            Handler::Concurrent(group, _) | Handler::Sequential(group) => group.span,
+        }
+    }
+}
+
+pub fn handler<'a>() -> impl Parser<'a, Handler> {
+    either(
+        either(
+            concurrent().map(|(group, concurrency)| Handler::Concurrent(group, concurrency)),
+            sequential().map(Handler::Sequential),
+        ),
+        pair(process_name.spanned(), optional(args()))
+            .map(|(name, args)| Handler::Process(name, args.unwrap_or_default())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arpx_job::task::process_names;
+    use crate::generic::span::spanned;
+
+    #[test]
+    fn test_handler_by_name() -> Result<(), String> {
+        assert_eq!(
+            handler().parse("notify;")?,
+            (";", Handler::Process(spanned("notify", 0), Vec::new()))
+        );
+
+        let Handler::Process(name, args) = handler().parse("notify(channel=\"ops\") :")?.output
+        else {
+            return Err("expected a process handler".to_string());
+        };
+
+        assert_eq!(name, spanned("notify", 0));
+        assert_eq!(args[0].name, spanned("channel", 7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_handler_blocks() -> Result<(), String> {
+        let Handler::Concurrent(group, _) = handler().parse("[ test; lint; ] :")?.output else {
+            return Err("expected a concurrent handler".to_string());
+        };
+
+        assert_eq!(process_names(&group.tasks), vec!["test", "lint"]);
+        assert_eq!(group.span, Span::new(0, 15));
+
+        let Handler::Sequential(group) = handler().parse("{ notify ? log; cleanup; };")?.output
+        else {
+            return Err("expected a sequential handler".to_string());
+        };
+
+        assert_eq!(process_names(&group.tasks), vec!["notify", "cleanup"]);
+        assert_eq!(group.span, Span::new(0, 26));
+        Ok(())
+    }
+}
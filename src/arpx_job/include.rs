@@ -0,0 +1,72 @@
+use crate::arpx_job::task::Task;
+use crate::generic::builtin_matchers::string::quoted_string;
+use crate::prelude::*;
+
+/// An `include "common/setup.arpx";` directive, which runs the tasks of
+/// another job script in its place. `parse_job` leaves `tasks` empty;
+/// `load_job` fills it in, and the spans of those tasks refer to the
+/// included file rather than to the file containing the directive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Include {
+    pub path: Spanned<String>,
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+pub fn include<'a>() -> impl Parser<'a, Include> {
+    whitespace_wrap(
+        left(
+            right(literal("include"), whitespace_wrap(quoted_string.spanned())),
+            literal(";"),
+        )
+        .spanned()
+        .validate(|path| {
+            if path.value.value.is_empty() {
+                Err(Rejection::new(
+                    path.value.span,
+                    "include paths cannot be empty",
+                ))
+            } else {
+                Ok(())
+            }
+        })
+        .map(|Spanned { value, span }| Include {
+            path: value,
+            tasks: Vec::new(),
+            span,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::ParseFailure;
+
+    #[test]
+    fn test_include() -> Result<(), String> {
+        assert_eq!(
+            include().parse(r#"include "common/setup.arpx"; build;"#)?,
+            (
+                "build;",
+                Include {
+                    path: Spanned::new("common/setup.arpx".to_string(), Span::new(8, 27)),
+                    tasks: Vec::new(),
+                    span: Span::new(0, 28),
+                }
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_path_cannot_be_empty() {
+        assert_eq!(
+            include().parse(r#"include "";"#),
+            Err(ParseFailure::rejected(
+                "",
+                Rejection::new(Span::new(8, 10), "include paths cannot be empty")
+            ))
+        );
+    }
+}
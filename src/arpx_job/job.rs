@@ -1,20 +1,195 @@
+use crate::arpx_job::macros::{definition, Macro};
 use crate::arpx_job::task::{task, Task};
+use crate::arpx_job::variables::{variable, Variable};
+use crate::generic::builtin_matchers::string::quoted_string;
+use crate::generic::builtin_matchers::whitespace::skip_trivia;
+use crate::generic::combinators::parser::{merge_failures, ParseFailure};
 use crate::prelude::*;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Job {
     pub tasks: Vec<Task>,
+    pub span: Span,
+    /// The `let` declarations, in source order.
+    pub variables: Vec<Variable>,
+    /// The `def` definitions, in source order.
+    pub macros: Vec<Macro>,
 }
 
+/// A top-level statement: a task, a variable declaration or a macro
+/// definition.
+#[allow(clippy::large_enum_variant)]
+enum Item {
+    Task(Task),
+    Variable(Variable),
+    Macro(Macro),
+}
+
+fn item<'a>() -> impl Parser<'a, Item> {
+    either(
+        either(
+            variable().map(Item::Variable),
+            definition().map(Item::Macro),
+        ),
+        task().map(Item::Task),
+    )
+}
+
+impl Job {
+    fn push(&mut self, item: Item) {
+        match item {
+            Item::Task(task) => self.tasks.push(task),
+            Item::Variable(variable) => self.variables.push(variable),
+            Item::Macro(definition) => self.macros.push(definition),
+        }
+    }
+}
+
+/// How deeply blocks may nest. Parsing recurses once per level, so the limit
+/// keeps pathological input from exhausting the stack.
+pub(crate) const MAX_NESTING: usize = 32;
+
 #[must_use]
 pub fn job<'a>() -> impl Parser<'a, Job> {
-    n(whitespace_wrap(task()), 0..).map(|tasks| Job { tasks })
+    let job = left(n(whitespace_wrap(item()), 0..), space0())
+        .spanned()
+        .map(|Spanned { value, span }| {
+            let mut job = Job {
+                tasks: Vec::new(),
+                span,
+                variables: Vec::new(),
+                macros: Vec::new(),
+            };
+
+            for item in value {
+                job.push(item);
+            }
+
+            job
+        });
+
+    move |input| {
+        check_nesting(input)?;
+        job.parse_input(input)
+    }
+}
+
+/// Parses a job like `job`, but instead of stopping at the first task it
+/// cannot parse, records the failure, skips past the next `;` or the end of
+/// the enclosing block and carries on through the rest of the input.
+pub fn recovering_job(input: Input<'_>) -> (Job, Vec<ParseFailure<'_>>) {
+    let item = whitespace_wrap(item());
+    let start = input;
+    let end = input.advance(input.as_str().len());
+    let mut input = input;
+    let mut job = Job {
+        tasks: Vec::new(),
+        span: start.span_to(end),
+        variables: Vec::new(),
+        macros: Vec::new(),
+    };
+    let mut failures = Vec::new();
+    let mut furthest = None;
+
+    if let Err(failure) = check_nesting(input) {
+        failures.push(failure);
+        input = end;
+    }
+
+    while !skip_trivia(input.as_str()).is_empty() {
+        match item.parse_input(input) {
+            Ok(parsed) => {
+                input = parsed.remaining;
+                job.push(parsed.output);
+                furthest = parsed.furthest;
+            }
+            Err(failure) => {
+                let failure = merge_failures(furthest.take(), failure);
+
+                input = synchronize(input, &failure);
+                failures.push(failure);
+            }
+        }
+    }
+
+    (job, failures)
+}
+
+/// Fails at the first block that opens more than `MAX_NESTING` levels deep.
+fn check_nesting(input: Input<'_>) -> Result<(), ParseFailure<'_>> {
+    let text = input.as_str();
+    let mut depth = 0;
+
+    for (span, next) in tokens(text) {
+        match next {
+            '[' | '{' if depth == MAX_NESTING => {
+                return Err(ParseFailure::rejected(
+                    &text[span.start..],
+                    Rejection::new(
+                        Span::new(input.offset() + span.start, input.offset() + span.end),
+                        format!("blocks cannot nest more than {} levels deep", MAX_NESTING),
+                    ),
+                ));
+            }
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the input just past the `;`, `]` or `}` that ends the construct
+/// `failure` happened in. A block opened before the failure is skipped as a
+/// whole, so that its remaining tasks are not read as tasks of their own.
+fn synchronize<'a>(input: Input<'a>, failure: &ParseFailure<'a>) -> Input<'a> {
+    let text = input.as_str();
+    let failed_at = text.len() - failure.remaining.len().min(text.len());
+    let mut depth = 0_usize;
+
+    for (span, next) in tokens(text) {
+        match next {
+            '[' | '{' => depth += 1,
+            ']' | '}' if span.start < failed_at || depth > 1 => {
+                depth = depth.saturating_sub(1);
+            }
+            ']' | '}' => return input.advance(span.end),
+            ';' if span.start >= failed_at && depth == 0 => return input.advance(span.end),
+            _ => {}
+        }
+    }
+
+    input.advance(text.len())
+}
+
+/// Yields the span and first character of each token in `text`, passing
+/// over comments and treating each quoted name as a single token, so that
+/// brackets and semicolons inside either are ignored.
+fn tokens(text: &str) -> impl Iterator<Item = (Span, char)> + '_ {
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        let start = text.len() - skip_trivia(&text[offset..]).len();
+        let next = text[start..].chars().next()?;
+
+        offset = match quoted_string(Input::new(&text[start..])) {
+            Ok(parsed) => start + parsed.remaining.offset(),
+            Err(_) => start + next.len_utf8(),
+        };
+
+        Some((Span::new(start, offset), next))
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arpx_job::handler::Handler;
     use crate::arpx_job::process::Process;
+    use crate::arpx_job::settings::Concurrency;
+    use crate::arpx_job::task::{process_names, Group};
+    use crate::generic::span::spanned;
 
     #[test]
     fn test_job() -> Result<(), String> {
@@ -32,51 +207,165 @@ mod tests {
             "",
             Job {
                 tasks: vec![
-                    Task {
-                        processes: vec![
-                            Process {
-                                log_monitors: Vec::new(),
-                                name: "loop1".to_string(),
-                                onsucceed: Some("loop2".to_string()),
-                                onfail: Some("loop3".to_string()),
-                            },
-                            Process {
-                                log_monitors: Vec::new(),
-                                name: "loop2".to_string(),
-                                onsucceed: Some("loop3".to_string()),
-                                onfail: Some("loop4".to_string()),
-                            },
-                        ],
-                    },
-                    Task {
-                        processes: vec![Process {
-                            log_monitors: Vec::new(),
-                            name: "loop3".to_string(),
-                            onsucceed: Some("loop4".to_string()),
-                            onfail: Some("loop5".to_string()),
-                        }],
-                    },
-                    Task {
-                        processes: vec![Process {
-                            log_monitors: Vec::new(),
-                            name: "loop6".to_string(),
-                            onsucceed: None,
-                            onfail: None,
-                        }],
-                    },
-                    Task {
-                        processes: vec![Process {
-                            log_monitors: Vec::new(),
-                            name: "loop7".to_string(),
-                            onsucceed: Some("loop8".to_string()),
-                            onfail: None,
-                        }],
-                    },
+                    Task::Concurrent(
+                        Group {
+                            tasks: vec![
+                                Task::Process(Process {
+                                    always: None,
+                                    args: Vec::new(),
+                                    branches: Vec::new(),
+                                    condition: None,
+                                    log_monitors: Vec::new(),
+                                    name: spanned("loop1", 31),
+                                    onsucceed: Some(Handler::Process(
+                                        spanned("loop2", 39),
+                                        Vec::new(),
+                                    )),
+                                    onfail: Some(Handler::Process(
+                                        spanned("loop3", 47),
+                                        Vec::new(),
+                                    )),
+                                    ontimeout: None,
+                                    retry: None,
+                                    span: Span::new(31, 53),
+                                    timeout: None,
+                                }),
+                                Task::Process(Process {
+                                    always: None,
+                                    args: Vec::new(),
+                                    branches: Vec::new(),
+                                    condition: None,
+                                    log_monitors: Vec::new(),
+                                    name: spanned("loop2", 70),
+                                    onsucceed: Some(Handler::Process(
+                                        spanned("loop3", 78),
+                                        Vec::new(),
+                                    )),
+                                    onfail: Some(Handler::Process(
+                                        spanned("loop4", 86),
+                                        Vec::new(),
+                                    )),
+                                    ontimeout: None,
+                                    retry: None,
+                                    span: Span::new(70, 92),
+                                    timeout: None,
+                                }),
+                            ],
+                            span: Span::new(13, 106),
+                        },
+                        Concurrency::default(),
+                    ),
+                    Task::Process(Process {
+                        always: None,
+                        args: Vec::new(),
+                        branches: Vec::new(),
+                        condition: None,
+                        log_monitors: Vec::new(),
+                        name: spanned("loop3", 119),
+                        onsucceed: Some(Handler::Process(spanned("loop4", 127), Vec::new())),
+                        onfail: Some(Handler::Process(spanned("loop5", 135), Vec::new())),
+                        ontimeout: None,
+                        retry: None,
+                        span: Span::new(119, 141),
+                        timeout: None,
+                    }),
+                    Task::Process(Process {
+                        always: None,
+                        args: Vec::new(),
+                        branches: Vec::new(),
+                        condition: None,
+                        log_monitors: Vec::new(),
+                        name: spanned("loop6", 154),
+                        onsucceed: None,
+                        onfail: None,
+                        ontimeout: None,
+                        retry: None,
+                        span: Span::new(154, 160),
+                        timeout: None,
+                    }),
+                    Task::Process(Process {
+                        always: None,
+                        args: Vec::new(),
+                        branches: Vec::new(),
+                        condition: None,
+                        log_monitors: Vec::new(),
+                        name: spanned("loop7", 173),
+                        onsucceed: Some(Handler::Process(spanned("loop8", 181), Vec::new())),
+                        onfail: None,
+                        ontimeout: None,
+                        retry: None,
+                        span: Span::new(173, 187),
+                        timeout: None,
+                    }),
                 ],
+                span: Span::new(0, 196),
+                variables: Vec::new(),
+                macros: Vec::new(),
             },
         );
 
         assert_eq!(job().parse(example)?, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_recovering_job() -> Result<(), String> {
+        let example = r#"loop1; loop2 ] loop3; [loop4 ? ;] "loop;5";"#;
+
+        let (job, failures) = recovering_job(Input::new(example));
+        let names = process_names(&job.tasks);
+        let failed_at: Vec<&str> = failures
+            .iter()
+            .map(|failure| failure.remaining.trim_start())
+            .collect();
+
+        assert_eq!(names, vec!["loop1", "loop3", "loop;5"]);
+        assert_eq!(
+            failed_at,
+            vec![r#"] loop3; [loop4 ? ;] "loop;5";"#, r#";] "loop;5";"#]
+        );
+        assert_eq!(job.span, Span::new(0, 43));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovering_job_skips_whole_blocks() -> Result<(), String> {
+        let example = r#"[loop1 ? ? "loop;]2"; { loop3; loop4; }] loop5;"#;
+
+        let (job, failures) = recovering_job(Input::new(example));
+
+        assert_eq!(job.tasks.len(), 1);
+        assert_eq!(job.tasks[0].processes()[0].name, spanned("loop5", 41));
+        assert_eq!(failures.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovering_job_without_errors() -> Result<(), String> {
+        let example = "\n  loop1 ? loop2;\n  [loop3; loop4;]\n";
+
+        assert_eq!(
+            recovering_job(Input::new(example)),
+            (job().parse(example)?.output, Vec::new())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nesting_limit() -> Result<(), String> {
+        let nested = |depth| format!("{}a; b;{}", "[a; ".repeat(depth), "]".repeat(depth));
+        let deepest = nested(MAX_NESTING);
+        let too_deep = nested(MAX_NESTING + 1);
+
+        assert!(job().parse(&deepest)?.remaining.as_str().is_empty());
+        assert_eq!(
+            job().parse(&too_deep).map_err(|failure| failure.rejection),
+            Err(Some(Spanned::new(
+                "blocks cannot nest more than 32 levels deep".to_string(),
+                Span::new(4 * MAX_NESTING, 4 * MAX_NESTING + 1),
+            )))
+        );
+        assert_eq!(recovering_job(Input::new(&too_deep)).1.len(), 1);
+        Ok(())
+    }
 }
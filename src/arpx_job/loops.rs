@@ -0,0 +1,141 @@
+use crate::arpx_job::task::{sequential, Task};
+use crate::generic::builtin_matchers::integer::positive_count;
+use crate::generic::builtin_matchers::literal::keyword;
+use crate::prelude::*;
+
+/// A block that runs its tasks several times in a row: a fixed number of
+/// times, as in `repeat 3 { ... }`, or until they all succeed, as in
+/// `repeat until success max 5 { ... }`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repeat {
+    /// How many times the tasks run, or with `until_success`, at most.
+    pub count: Spanned<u32>,
+    pub until_success: bool,
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+/// A block that runs its tasks over and over until the job is stopped:
+/// `forever { ... }`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Forever {
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+pub fn repeat<'a>() -> impl Parser<'a, Repeat> {
+    let count = either(
+        right(
+            pair(keyword("until"), pair(keyword("success"), keyword("max"))),
+            loop_count(),
+        )
+        .map(|count| (count, true)),
+        loop_count().map(|count| (count, false)),
+    );
+
+    whitespace_wrap(
+        pair(right(keyword("repeat"), count), sequential())
+            .spanned()
+            .map(|Spanned { value, span }| {
+                let ((count, until_success), body) = value;
+
+                Repeat {
+                    count,
+                    until_success,
+                    tasks: body.tasks,
+                    span: Span::new(span.start, body.span.end),
+                }
+            }),
+    )
+}
+
+pub fn forever<'a>() -> impl Parser<'a, Forever> {
+    whitespace_wrap(right(keyword("forever"), sequential()).spanned().map(
+        |Spanned { value, span }| Forever {
+            span: Span::new(span.start, value.span.end),
+            tasks: value.tasks,
+        },
+    ))
+}
+
+fn loop_count<'a>() -> impl Parser<'a, Spanned<u32>> {
+    positive_count(
+        "repeat count",
+        "a loop must run its tasks at least once",
+        "remove the loop to skip its tasks",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arpx_job::task::process_names;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
+    use crate::generic::span::spanned;
+
+    #[test]
+    fn test_repeat() -> Result<(), String> {
+        let parsed = repeat().parse("repeat 3 { a; b; } c;")?;
+
+        assert_eq!(parsed.remaining.as_str(), "c;");
+        assert_eq!(parsed.output.count, Spanned::new(3, Span::new(7, 8)));
+        assert!(!parsed.output.until_success);
+        assert_eq!(process_names(&parsed.output.tasks), vec!["a", "b"]);
+        assert_eq!(parsed.output.span, Span::new(0, 18));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat_until_success() -> Result<(), String> {
+        let parsed = repeat().parse("repeat  until success\n max 5 { flaky; }")?;
+
+        assert_eq!(parsed.output.count, Spanned::new(5, Span::new(27, 28)));
+        assert!(parsed.output.until_success);
+        assert_eq!(process_names(&parsed.output.tasks), vec!["flaky"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_forever() -> Result<(), String> {
+        let parsed = forever().parse("forever\n{ soak; } ")?;
+
+        assert_eq!(
+            parsed.output.tasks[0].processes()[0].name,
+            spanned("soak", 10)
+        );
+        assert_eq!(parsed.output.span, Span::new(0, 17));
+        assert_eq!(forever().parse("forever{ a; }")?.output.tasks.len(), 1);
+        assert!(forever().parse("forever;").is_err());
+        assert!(forever().parse("forevermore { soak; }").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_bounds() {
+        assert_eq!(
+            repeat().parse("repeat 0 { a; }"),
+            Err(ParseFailure::rejected(
+                "{ a; }",
+                Rejection::new(Span::new(7, 8), "a loop must run its tasks at least once")
+                    .with_note("remove the loop to skip its tasks")
+            ))
+        );
+        assert_eq!(
+            repeat().parse("repeat until success max 0 { a; }"),
+            Err(ParseFailure::rejected(
+                "{ a; }",
+                Rejection::new(Span::new(25, 26), "a loop must run its tasks at least once")
+                    .with_note("remove the loop to skip its tasks")
+            ))
+        );
+        assert_eq!(
+            repeat()
+                .parse("repeat 99999999999 { a; }")
+                .map_err(|failure| failure.expected),
+            Err(vec![
+                Expected::Literal("until"),
+                Expected::Label("repeat count")
+            ])
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use crate::arpx_job::args::Arg;
+use crate::arpx_job::condition::When;
+use crate::arpx_job::handler::Handler;
+use crate::arpx_job::include::Include;
+use crate::arpx_job::job::{Job, MAX_NESTING};
+use crate::arpx_job::loops::{Forever, Repeat};
+use crate::arpx_job::process::{bare_name, Process};
+use crate::arpx_job::task::{sequential, Task};
+use crate::generic::builtin_matchers::literal::keyword;
+use crate::prelude::*;
+
+/// How many tasks macro calls may copy into a job in total, so that macros
+/// that call each other several times over cannot blow up exponentially.
+/// Each copy of a macro's body is counted before it is made.
+const MAX_EXPANDED_TASKS: usize = 100_000;
+
+/// A `def ci(target) { build(target) : page; test(target); }` definition.
+/// Later tasks named like the macro, as in `ci(target="web");`, call it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Macro {
+    pub name: Spanned<String>,
+    pub params: Vec<Spanned<String>>,
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+/// A call to a macro, which `parse_job` expands into a copy of the macro's
+/// tasks. The spans of those tasks refer to the macro's body, while the
+/// arguments are those of the call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invocation {
+    pub name: Spanned<String>,
+    pub args: Vec<Arg>,
+    /// The span of the `def` the tasks were expanded from.
+    pub definition: Span,
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+pub fn definition<'a>() -> impl Parser<'a, Macro> {
+    whitespace_wrap(
+        pair(
+            right(
+                keyword("def"),
+                whitespace_wrap(bare_name("macro name").spanned()),
+            ),
+            pair(params(), sequential()),
+        )
+        .spanned()
+        .map(|Spanned { value, span }| {
+            let (name, (params, body)) = value;
+
+            Macro {
+                name,
+                params,
+                span: Span::new(span.start, body.span.end),
+                tasks: body.tasks,
+            }
+        }),
+    )
+}
+
+/// Parses a parenthesized, comma-separated list of parameter names, with
+/// an optional trailing comma.
+fn params<'a>() -> impl Parser<'a, Vec<Spanned<String>>> {
+    let param = || whitespace_wrap(bare_name("parameter name").spanned());
+    let list = pair(param(), n(right(literal(","), param()), 0..))
+        .map(|(first, rest)| std::iter::once(first).chain(rest).collect());
+
+    right(
+        whitespace_wrap(literal("(")),
+        left(
+            optional(left(list, optional(literal(",")))),
+            whitespace_wrap(literal(")")),
+        ),
+    )
+    .map(Option::unwrap_or_default)
+    .validate(|params: &Vec<Spanned<String>>| {
+        reject_duplicates(
+            params,
+            |param| Spanned::new(param.value.as_str(), param.span),
+            |name| format!("`{}` is already given", name),
+        )
+    })
+}
+
+/// Replaces each call to a macro in `job`, which was parsed from `source`,
+/// with an `Invocation` holding the macro's tasks. A task calls a macro if
+/// its bare name is that of a macro defined before it; quoting the name
+/// runs a process of that name instead. Returns why each call that could
+/// not be expanded is invalid, in source order.
+pub(crate) fn expand(job: &mut Job, source: &str) -> Vec<Rejection> {
+    let mut expander = Expander {
+        source,
+        macros: &job.macros,
+        expanded: 0,
+        depth: 0,
+        rejections: Vec::new(),
+    };
+
+    for task in &mut job.tasks {
+        expander.task(task);
+    }
+
+    expander
+        .rejections
+        .sort_by_key(|rejection| rejection.label.span.start);
+    expander.rejections
+}
+
+struct Expander<'j> {
+    source: &'j str,
+    macros: &'j [Macro],
+    /// How many tasks have been copied from macro bodies so far.
+    expanded: usize,
+    depth: usize,
+    rejections: Vec<Rejection>,
+}
+
+impl<'j> Expander<'j> {
+    fn task(&mut self, task: &mut Task) {
+        match task {
+            Task::Process(process) => match self.lookup(&process.name) {
+                Some(definition) => {
+                    if let Some(invocation) = self.call(process, definition) {
+                        *task = Task::Invocation(invocation);
+                    }
+                }
+                None => self.handlers(process),
+            },
+            Task::Concurrent(group, _) | Task::Sequential(group) => {
+                for task in &mut group.tasks {
+                    self.task(task);
+                }
+            }
+            Task::Repeat(Repeat { tasks, .. })
+            | Task::Forever(Forever { tasks, .. })
+            | Task::When(When { tasks, .. }) => {
+                for task in tasks {
+                    self.task(task);
+                }
+            }
+            Task::Include(_) | Task::Invocation(_) => {}
+        }
+    }
+
+    /// Handlers only take process names, so a macro named as a handler
+    /// would silently run a process instead; that is rejected.
+    fn handlers(&mut self, process: &mut Process) {
+        for handler in process.handlers_mut() {
+            match handler {
+                Handler::Process(name, _) => {
+                    if self.lookup(name).is_some() {
+                        self.rejections.push(
+                            Rejection::new(name.span, "macros can only be called as tasks")
+                                .with_note(format!(
+                                    "call it from a block instead, as in `{{ {}; }}`",
+                                    name.value
+                                )),
+                        );
+                    }
+                }
+                Handler::Concurrent(group, _) | Handler::Sequential(group) => {
+                    for task in &mut group.tasks {
+                        self.task(task);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, process: &mut Process, definition: &Macro) -> Option<Invocation> {
+        let rejected = self.rejections.len();
+        let decorated = process.always.is_some()
+            || !process.branches.is_empty()
+            || process.condition.is_some()
+            || !process.log_monitors.is_empty()
+            || process.onfail.is_some()
+            || process.onsucceed.is_some()
+            || process.ontimeout.is_some()
+            || process.retry.is_some()
+            || process.timeout.is_some();
+
+        if decorated {
+            self.rejections.push(
+                Rejection::new(
+                    process.span,
+                    "macro calls cannot have handlers, modifiers, conditions or log monitors",
+                )
+                .with_note("add them to the tasks in the macro's body instead"),
+            );
+        }
+
+        for arg in &process.args {
+            if !definition
+                .params
+                .iter()
+                .any(|param| param.value == arg.name.value)
+            {
+                self.rejections.push(Rejection::new(
+                    arg.name.span,
+                    format!(
+                        "`{}` has no parameter named `{}`",
+                        definition.name.value, arg.name.value
+                    ),
+                ));
+            }
+        }
+
+        for param in &definition.params {
+            if !process.args.iter().any(|arg| arg.name.value == param.value) {
+                self.rejections.push(Rejection::new(
+                    process.name.span,
+                    format!("missing argument `{}`", param.value),
+                ));
+            }
+        }
+
+        if self.depth == MAX_NESTING {
+            self.rejections.push(Rejection::new(
+                process.name.span,
+                format!(
+                    "macro calls cannot nest more than {} levels deep",
+                    MAX_NESTING
+                ),
+            ));
+        }
+
+        let size = size(&definition.tasks);
+
+        if self.rejections.len() == rejected && self.expanded + size > MAX_EXPANDED_TASKS {
+            self.rejections.push(Rejection::new(
+                process.name.span,
+                format!(
+                    "macro calls cannot expand into more than {} tasks",
+                    MAX_EXPANDED_TASKS
+                ),
+            ));
+        }
+
+        if self.rejections.len() > rejected {
+            return None;
+        }
+
+        self.expanded += size;
+        self.depth += 1;
+
+        let mut tasks = definition.tasks.clone();
+
+        for task in &mut tasks {
+            self.task(task);
+        }
+
+        self.depth -= 1;
+
+        Some(Invocation {
+            name: process.name.clone(),
+            args: std::mem::take(&mut process.args),
+            definition: definition.span,
+            tasks,
+            span: process.span,
+        })
+    }
+
+    /// Finds the macro a bare `name` calls: the last one defined before it.
+    fn lookup(&self, name: &Spanned<String>) -> Option<&'j Macro> {
+        let macros = self.macros;
+
+        if self.source[name.span.start..].starts_with('"') {
+            return None;
+        }
+
+        macros.iter().rev().find(|definition| {
+            definition.name.value == name.value && definition.span.end <= name.span.start
+        })
+    }
+}
+
+/// Counts `tasks` and every task nested in them, including those in
+/// handler blocks.
+fn size(tasks: &[Task]) -> usize {
+    tasks
+        .iter()
+        .map(|task| {
+            1 + match task {
+                Task::Process(process) => process
+                    .handlers()
+                    .map(|handler| match handler {
+                        Handler::Process(..) => 0,
+                        Handler::Concurrent(group, _) | Handler::Sequential(group) => {
+                            size(&group.tasks)
+                        }
+                    })
+                    .sum(),
+                Task::Concurrent(group, _) | Task::Sequential(group) => size(&group.tasks),
+                Task::Repeat(Repeat { tasks, .. })
+                | Task::Forever(Forever { tasks, .. })
+                | Task::When(When { tasks, .. })
+                | Task::Include(Include { tasks, .. })
+                | Task::Invocation(Invocation { tasks, .. }) => size(tasks),
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arpx_job::job::job;
+    use crate::generic::combinators::parser::ParseFailure;
+    use crate::generic::span::spanned;
+
+    fn expanded(source: &str) -> Result<(Job, Vec<String>), String> {
+        let mut parsed = job().parse(source)?.output;
+        let rejections = expand(&mut parsed, source)
+            .into_iter()
+            .map(|rejection| rejection.label.value)
+            .collect();
+
+        Ok((parsed, rejections))
+    }
+
+    #[test]
+    fn test_definition() -> Result<(), String> {
+        let parsed = definition().parse("def ci(x, y,) { build(x); } rest;")?;
+
+        assert_eq!(parsed.remaining.as_str(), "rest;");
+        assert_eq!(parsed.output.name, spanned("ci", 4));
+        assert_eq!(
+            parsed.output.params,
+            vec![spanned("x", 7), spanned("y", 10)]
+        );
+        assert_eq!(parsed.output.tasks[0].span(), Span::new(16, 25));
+        assert_eq!(parsed.output.span, Span::new(0, 27));
+        assert_eq!(
+            definition().parse("def ci(x, x) {}"),
+            Err(ParseFailure::rejected(
+                "{}",
+                Rejection::new(Span::new(10, 11), "`x` is already given")
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand() -> Result<(), String> {
+        let source = "def ci(x) { build(x) ? test; } ci(x=1); [ci(x=2); \"ci\";]";
+
+        let (parsed, rejections) = expanded(source)?;
+
+        assert_eq!(rejections, Vec::<String>::new());
+        assert!(matches!(
+            &parsed.tasks[0],
+            Task::Invocation(invocation) if invocation.name == spanned("ci", 31)
+                && invocation.definition == Span::new(0, 30)
+                && invocation.args[0].name == spanned("x", 34)
+                && invocation.tasks[0].span() == Span::new(12, 28)
+        ));
+        assert!(matches!(
+            &parsed.tasks[1],
+            Task::Concurrent(group, _) if matches!(group.tasks[0], Task::Invocation(_))
+                && matches!(group.tasks[1], Task::Process(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_macros_only_call_earlier_macros() -> Result<(), String> {
+        let source = "a; def a() { a; } def b() { a; } b;";
+
+        let (parsed, _) = expanded(source)?;
+        let Task::Invocation(b) = &parsed.tasks[1] else {
+            return Err(format!("expected a call, found {:?}", parsed.tasks[1]));
+        };
+        let Task::Invocation(a) = &b.tasks[0] else {
+            return Err(format!("expected a call, found {:?}", b.tasks[0]));
+        };
+
+        assert!(matches!(parsed.tasks[0], Task::Process(_)));
+        assert!(matches!(a.tasks[0], Task::Process(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_calls() -> Result<(), String> {
+        let source = "def ci(x) { a; } ci(y=1); ci ? b; c ? ci;";
+
+        assert_eq!(
+            expanded(source)?.1,
+            vec![
+                "missing argument `x`",
+                "`ci` has no parameter named `y`",
+                "macro calls cannot have handlers, modifiers, conditions or log monitors",
+                "missing argument `x`",
+                "macros can only be called as tasks",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_limits() -> Result<(), String> {
+        let doubling: String = (1..13)
+            .map(|level| format!("def m{}() {{ m{}; m{}; }} ", level, level - 1, level - 1))
+            .collect();
+        let wide = "a; ".repeat(500);
+
+        let source = format!("def m0() {{ {}}} {}m12;", wide, doubling);
+
+        assert_eq!(
+            expanded(&source)?.1[0],
+            "macro calls cannot expand into more than 100000 tasks"
+        );
+
+        let source = format!("def m0() {{ {}}} {}m6;", wide, doubling);
+        let (parsed, rejections) = expanded(&source)?;
+
+        assert_eq!(rejections, Vec::<String>::new());
+        assert_eq!(parsed.tasks[0].processes().len(), 500 << 6);
+
+        let chained: String = (1..40)
+            .map(|level| format!("def m{}() {{ m{}; }} ", level, level - 1))
+            .collect();
+        let source = format!("def m0() {{ a; }} {}m39;", chained);
+
+        assert_eq!(
+            expanded(&source)?.1,
+            vec!["macro calls cannot nest more than 32 levels deep"]
+        );
+        Ok(())
+    }
+}
@@ -1,7 +1,32 @@
+mod args;
+mod branch;
+mod condition;
+mod handler;
+mod include;
 mod job;
+mod loops;
+mod macros;
 mod process;
+mod retry;
+mod settings;
 mod task;
+mod variables;
 
-pub use crate::arpx_job::job::{job, Job};
-pub use crate::arpx_job::process::Process;
-pub use crate::arpx_job::task::{task, Task};
+pub use crate::arpx_job::args::{Arg, Number, Value};
+pub use crate::arpx_job::branch::{Branch, ExitCodes};
+pub use crate::arpx_job::condition::{condition, when, Condition, EvaluationError, Operand, When};
+pub use crate::arpx_job::handler::{handler, Handler};
+pub use crate::arpx_job::include::{include, Include};
+pub use crate::arpx_job::job::{job, recovering_job, Job};
+pub use crate::arpx_job::loops::{forever, repeat, Forever, Repeat};
+pub(crate) use crate::arpx_job::macros::expand;
+pub use crate::arpx_job::macros::{definition, Invocation, Macro};
+pub use crate::arpx_job::process::{format_name, Process};
+pub(crate) use crate::arpx_job::process::{is_name_continue, is_name_start};
+pub use crate::arpx_job::retry::{Backoff, Retry};
+pub use crate::arpx_job::settings::{Concurrency, FailurePolicy, Mode};
+#[cfg(test)]
+pub(crate) use crate::arpx_job::task::process_names;
+pub use crate::arpx_job::task::{task, Group, Task};
+pub(crate) use crate::arpx_job::variables::interpolate;
+pub use crate::arpx_job::variables::{variable, Variable};
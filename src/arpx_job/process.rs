@@ -1,53 +1,119 @@
+use std::mem;
+use std::time::Duration;
+
+use crate::arpx_job::args::{args, Arg};
+use crate::arpx_job::branch::{branches, Branch};
+use crate::arpx_job::condition::{when_clause, Condition};
+use crate::arpx_job::handler::{handler, Handler};
+use crate::arpx_job::retry::{retry, Retry};
+use crate::generic::builtin_matchers::duration::duration;
+use crate::generic::builtin_matchers::string::{quote, quoted_string};
+use crate::generic::combinators::parser::{Expected, ParseFailure};
 use crate::prelude::*;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Process {
-    pub log_monitors: Vec<String>,
-    pub name: String,
-    pub onfail: Option<String>,
-    pub onsucceed: Option<String>,
+    /// Runs after whichever other handler fired, whatever the outcome.
+    pub always: Option<Handler>,
+    pub args: Vec<Arg>,
+    /// Handlers for particular exit codes, tried in order. `onsucceed` and
+    /// `onfail` remain as shorthands for success and any other exit.
+    pub branches: Vec<Branch>,
+    /// A `when ...;` after the process's `;`, which it only runs if true.
+    pub condition: Option<Spanned<Condition>>,
+    pub log_monitors: Vec<Spanned<String>>,
+    pub name: Spanned<String>,
+    pub onfail: Option<Handler>,
+    pub onsucceed: Option<Handler>,
+    pub ontimeout: Option<Handler>,
+    pub retry: Option<Spanned<Retry>>,
+    pub span: Span,
+    pub timeout: Option<Spanned<Duration>>,
 }
 
-pub fn concurrent_processes<'a>() -> impl Parser<'a, Vec<Process>> {
-    right(
-        whitespace_wrap(literal("[")),
-        left(
-            n(single_process(), 2..).map(|result| result.into_iter().flatten().collect()),
-            whitespace_wrap(literal("]")),
-        ),
-    )
+impl Process {
+    /// Returns the handlers of this process in the order they are tried:
+    /// its exit code branches, then `onsucceed`, `onfail`, `ontimeout` and
+    /// `always`.
+    pub fn handlers(&self) -> impl Iterator<Item = &Handler> {
+        self.branches
+            .iter()
+            .map(|branch| &branch.target)
+            .chain(&self.onsucceed)
+            .chain(&self.onfail)
+            .chain(&self.ontimeout)
+            .chain(&self.always)
+    }
+
+    /// Returns the handlers of this process mutably, in the same order as
+    /// `handlers`.
+    pub fn handlers_mut(&mut self) -> impl Iterator<Item = &mut Handler> {
+        self.branches
+            .iter_mut()
+            .map(|branch| &mut branch.target)
+            .chain(&mut self.onsucceed)
+            .chain(&mut self.onfail)
+            .chain(&mut self.ontimeout)
+            .chain(&mut self.always)
+    }
 }
 
-pub fn single_process<'a>() -> impl Parser<'a, Vec<Process>> {
-    pair(process_without_log_monitors(), log_monitors()).map(|(process_vec, log_monitors)| {
-        match process_vec.first() {
-            Some(process) => vec![Process {
-                log_monitors,
-                name: process.name.clone(),
-                onfail: process.onfail.clone(),
-                onsucceed: process.onsucceed.clone(),
-            }],
-            None => panic!(),
+pub fn single_process<'a>() -> impl Parser<'a, Process> {
+    pair(
+        process_without_log_monitors(),
+        pair(
+            optional(whitespace_wrap(when_clause().spanned())),
+            log_monitors(),
+        ),
+    )
+    .map(|(process, (clause, log_monitors))| {
+        let end = log_monitors
+            .last()
+            .map(|monitor| monitor.span.end)
+            .or(clause.as_ref().map(|clause| clause.span.end))
+            .unwrap_or(process.span.end);
+
+        Process {
+            condition: clause.map(|clause| clause.value),
+            log_monitors,
+            span: Span::new(process.span.start, end),
+            ..process
         }
     })
 }
 
-pub fn log_monitors<'a>() -> impl Parser<'a, Vec<String>> {
-    n(whitespace_wrap(right(literal("@"), process_name)), 0..)
+pub fn log_monitors<'a>() -> impl Parser<'a, Vec<Spanned<String>>> {
+    n(
+        whitespace_wrap(right(literal("@"), process_name.spanned())),
+        0..,
+    )
 }
 
-fn process_without_log_monitors<'a>() -> impl Parser<'a, Vec<Process>> {
+fn process_without_log_monitors<'a>() -> impl Parser<'a, Process> {
     whitespace_wrap(
-        terminating_semicolon(pair(process_name, process_predicate())).map(
-            |(name, (onsucceed, onfail))| {
-                vec![Process {
-                    log_monitors: Vec::new(),
-                    name,
-                    onfail,
-                    onsucceed,
-                }]
-            },
-        ),
+        terminating_semicolon(pair(
+            pair(process_name.spanned(), optional(args())),
+            process_predicate(),
+        ))
+        .spanned()
+        .map(|Spanned { value, span }| {
+            let ((name, args), predicate) = value;
+
+            Process {
+                always: predicate.always,
+                args: args.unwrap_or_default(),
+                branches: predicate.branches,
+                condition: None,
+                log_monitors: Vec::new(),
+                name,
+                onfail: predicate.onfail,
+                onsucceed: predicate.onsucceed,
+                ontimeout: predicate.ontimeout,
+                retry: predicate.retry,
+                span,
+                timeout: predicate.timeout,
+            }
+        }),
     )
 }
 
@@ -56,53 +122,213 @@ where
     P: 'a + Parser<'a, A>,
     A: 'a,
 {
-    left(parser, literal(";"))
+    left(past_trivia(parser), right(space0(), literal(";")))
 }
 
-fn process_name(input: &str) -> ParseResult<String> {
-    let mut matched = String::new();
-    let chars = input.chars();
+/// Parses a process name, either bare or double-quoted. Bare names start
+/// with a letter or `_` and continue with letters, digits, `_` or `-`, where
+/// letters and digits are any characters with the Unicode `Alphabetic` or
+/// `Numeric` property, so that names are close to Unicode identifiers but
+/// may also be kebab-cased. Any other non-empty name must be quoted.
+pub(crate) fn process_name(input: Input<'_>) -> ParseResult<'_, String> {
+    let text = input.as_str();
+
+    match text.chars().next() {
+        Some('"') => {
+            let parsed = quoted_string(input)?;
+
+            if parsed.output.is_empty() {
+                Err(ParseFailure::rejected(
+                    text,
+                    Rejection::new(
+                        input.span_to(parsed.remaining),
+                        "process names cannot be empty",
+                    ),
+                ))
+            } else {
+                Ok(parsed)
+            }
+        }
+        _ => bare_name("process name").parse_input(input),
+    }
+}
 
-    for next in chars {
-        if next.is_alphanumeric() || next == '-' || next == '_' {
-            matched.push(next);
-        } else {
-            break;
+/// Writes `name` the way a job script would spell it: bare if it is a valid
+/// bare name, and quoted otherwise.
+#[must_use]
+pub fn format_name(name: &str) -> String {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(first) if is_name_start(first) && chars.all(is_name_continue) => name.to_string(),
+        _ => quote(name),
+    }
+}
+
+/// Parses a bare name, as used for processes, arguments, variables, macros
+/// and their parameters, failing with `label` as the expectation.
+pub(crate) fn bare_name<'a>(label: &'static str) -> impl Parser<'a, String> {
+    move |input: Input<'a>| {
+        let text = input.as_str();
+
+        match text.chars().next() {
+            Some(first) if is_name_start(first) => {
+                let len = text
+                    .find(|next: char| !is_name_continue(next))
+                    .unwrap_or(text.len());
+
+                Ok(Parsed::new(input.advance(len), text[..len].to_string()))
+            }
+            _ => Err(ParseFailure::new(text, vec![Expected::Label(label)])),
         }
     }
+}
+
+/// Names are letters, digits, `_` and `-`, as classified by `char`, and may
+/// not start with a digit or `-`. This is narrower than Unicode identifiers:
+/// combining marks (as in a decomposed `a\u{301}`) end a name, so such names
+/// have to be written precomposed or quoted.
+pub(crate) fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
 
-    let next_index = matched.len();
+pub(crate) fn is_name_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Everything that may follow a process name and its arguments: modifiers
+/// in any order, then handlers in the order they are listed here.
+struct Predicate {
+    retry: Option<Spanned<Retry>>,
+    timeout: Option<Spanned<Duration>>,
+    branches: Vec<Branch>,
+    onsucceed: Option<Handler>,
+    onfail: Option<Handler>,
+    ontimeout: Option<Handler>,
+    always: Option<Handler>,
+}
 
-    Ok((&input[next_index..], matched))
+enum Modifier {
+    Retry(Retry),
+    Timeout(Duration),
 }
 
-fn process_predicate<'a>() -> impl Parser<'a, (Option<String>, Option<String>)> {
-    pair(optional(onsucceed()), optional(onfail()))
+fn process_predicate<'a>() -> impl Parser<'a, Predicate> {
+    pair(
+        modifiers(),
+        pair(
+            branches(),
+            pair(
+                optional(onsucceed()),
+                pair(
+                    optional(onfail()),
+                    pair(optional(ontimeout()), optional(always())),
+                ),
+            ),
+        ),
+    )
+    .map(
+        |((retry, timeout), (branches, (onsucceed, (onfail, (ontimeout, always)))))| Predicate {
+            retry,
+            timeout,
+            branches,
+            onsucceed,
+            onfail,
+            ontimeout,
+            always,
+        },
+    )
+    .validate(
+        |predicate| match (&predicate.ontimeout, &predicate.timeout) {
+            (Some(ontimeout), None) => Err(Rejection::new(
+                ontimeout.span(),
+                "this handler never runs because the process has no timeout",
+            )
+            .with_note("add a modifier such as `timeout=10m` after the process name")),
+            _ => Ok(()),
+        },
+    )
 }
 
-fn onsucceed<'a>() -> impl Parser<'a, String> {
-    right(whitespace_wrap(literal("?")), process_name)
+type Modifiers = (Option<Spanned<Retry>>, Option<Spanned<Duration>>);
+
+/// Parses `retry(...)` and `timeout=...` modifiers, each of which may be
+/// given once, in either order.
+fn modifiers<'a>() -> impl Parser<'a, Modifiers> {
+    let modifier = either(
+        retry().map(Modifier::Retry),
+        right(
+            pair(literal("timeout"), whitespace_wrap(literal("="))),
+            duration,
+        )
+        .map(Modifier::Timeout),
+    );
+
+    n(whitespace_wrap(modifier.spanned()), 0..)
+        .validate(|modifiers| {
+            reject_duplicates(
+                modifiers,
+                |modifier| Spanned::new(mem::discriminant(&modifier.value), modifier.span),
+                |_| "this modifier is already given".to_string(),
+            )
+        })
+        .map(|modifiers| {
+            let (mut retry, mut timeout) = (None, None);
+
+            for Spanned { value, span } in modifiers {
+                match value {
+                    Modifier::Retry(value) => retry = Some(Spanned::new(value, span)),
+                    Modifier::Timeout(value) => timeout = Some(Spanned::new(value, span)),
+                }
+            }
+
+            (retry, timeout)
+        })
 }
 
-fn onfail<'a>() -> impl Parser<'a, String> {
-    right(whitespace_wrap(literal(":")), process_name)
+fn onsucceed<'a>() -> impl Parser<'a, Handler> {
+    right(whitespace_wrap(literal("?")), handler())
+}
+
+fn onfail<'a>() -> impl Parser<'a, Handler> {
+    right(whitespace_wrap(literal(":")), handler())
+}
+
+fn ontimeout<'a>() -> impl Parser<'a, Handler> {
+    right(whitespace_wrap(literal("~")), handler())
+}
+
+fn always<'a>() -> impl Parser<'a, Handler> {
+    right(whitespace_wrap(literal("!")), handler())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arpx_job::branch::ExitCodes;
+    use crate::arpx_job::condition::Operand;
     use crate::arpx_job::process::Process;
+    use crate::arpx_job::retry::Backoff;
+    use crate::generic::span::spanned;
 
     #[test]
     fn test_process() -> Result<(), String> {
         let example = "loop1;";
 
-        let expected = vec![Process {
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
             log_monitors: Vec::new(),
-            name: "loop1".to_string(),
+            name: spanned("loop1", 0),
             onsucceed: None,
             onfail: None,
-        }];
+            ontimeout: None,
+            retry: None,
+            span: Span::new(0, 6),
+            timeout: None,
+        };
 
         assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
@@ -112,12 +338,20 @@ mod tests {
     fn test_process_with_only_onsucceed() -> Result<(), String> {
         let example = "loop1 ? loop2;";
 
-        let expected = vec![Process {
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
             log_monitors: Vec::new(),
-            name: "loop1".to_string(),
-            onsucceed: Some("loop2".to_string()),
+            name: spanned("loop1", 0),
+            onsucceed: Some(Handler::Process(spanned("loop2", 8), Vec::new())),
             onfail: None,
-        }];
+            ontimeout: None,
+            retry: None,
+            span: Span::new(0, 14),
+            timeout: None,
+        };
 
         assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
@@ -127,12 +361,20 @@ mod tests {
     fn test_process_with_only_onfail() -> Result<(), String> {
         let example = "loop1 : loop3;";
 
-        let expected = vec![Process {
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
             log_monitors: Vec::new(),
-            name: "loop1".to_string(),
+            name: spanned("loop1", 0),
             onsucceed: None,
-            onfail: Some("loop3".to_string()),
-        }];
+            onfail: Some(Handler::Process(spanned("loop3", 8), Vec::new())),
+            ontimeout: None,
+            retry: None,
+            span: Span::new(0, 14),
+            timeout: None,
+        };
 
         assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
@@ -142,124 +384,427 @@ mod tests {
     fn test_process_with_onsucceed_and_onfail() -> Result<(), String> {
         let example = "loop1 ? loop2 : loop3;";
 
-        let expected = vec![Process {
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
             log_monitors: Vec::new(),
-            name: "loop1".to_string(),
-            onsucceed: Some("loop2".to_string()),
-            onfail: Some("loop3".to_string()),
-        }];
+            name: spanned("loop1", 0),
+            onsucceed: Some(Handler::Process(spanned("loop2", 8), Vec::new())),
+            onfail: Some(Handler::Process(spanned("loop3", 16), Vec::new())),
+            ontimeout: None,
+            retry: None,
+            span: Span::new(0, 22),
+            timeout: None,
+        };
 
         assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
     }
 
     #[test]
-    fn test_concurrent_processes() -> Result<(), String> {
-        let example = r#"
-            [
-                loop1 ? loop2 : loop3;
-                loop2 ? loop3 : loop4;
-                loop3 ? loop4 : loop5;
-            ]
-        "#;
+    fn test_process_with_log_monitors() -> Result<(), String> {
+        let example = "loop1; @foo @bar @baz";
 
-        let expected = vec![
-            Process {
-                log_monitors: Vec::new(),
-                name: "loop1".to_string(),
-                onsucceed: Some("loop2".to_string()),
-                onfail: Some("loop3".to_string()),
-            },
-            Process {
-                log_monitors: Vec::new(),
-                name: "loop2".to_string(),
-                onsucceed: Some("loop3".to_string()),
-                onfail: Some("loop4".to_string()),
-            },
-            Process {
-                log_monitors: Vec::new(),
-                name: "loop3".to_string(),
-                onsucceed: Some("loop4".to_string()),
-                onfail: Some("loop5".to_string()),
-            },
-        ];
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
+            log_monitors: vec![spanned("foo", 8), spanned("bar", 13), spanned("baz", 18)],
+            name: spanned("loop1", 0),
+            onsucceed: None,
+            onfail: None,
+            ontimeout: None,
+            retry: None,
+            span: Span::new(0, 21),
+            timeout: None,
+        };
 
-        assert_eq!(concurrent_processes().parse(example)?, ("", expected));
+        assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
     }
 
     #[test]
-    fn test_concurrent_processes_must_have_2_single_processes() -> Result<(), String> {
-        let example_1 = "[loop1 ? loop2 : loop3;]";
-        let example_2 = "[loop1 ? loop2 : loop3; loop2 ? loop3;]";
+    fn test_process_name() -> Result<(), String> {
+        assert_eq!(
+            process_name.parse("loop-1_a;")?,
+            (";", "loop-1_a".to_string())
+        );
+        assert_eq!(process_name.parse("_build")?, ("", "_build".to_string()));
+        assert_eq!(
+            process_name.parse("日本語 ?")?,
+            (" ?", "日本語".to_string())
+        );
+        assert_eq!(
+            process_name.parse("1loop"),
+            Err(ParseFailure::new(
+                "1loop",
+                vec![Expected::Label("process name")]
+            ))
+        );
+        assert_eq!(
+            process_name.parse("-loop"),
+            Err(ParseFailure::new(
+                "-loop",
+                vec![Expected::Label("process name")]
+            ))
+        );
+        assert_eq!(
+            process_name.parse("a\u{301};")?,
+            ("\u{301};", "a".to_string())
+        );
+        assert_eq!(process_name.parse("\u{e1};")?, (";", "\u{e1}".to_string()));
+        Ok(())
+    }
 
-        let expected_2 = vec![
-            Process {
-                log_monitors: Vec::new(),
-                name: "loop1".to_string(),
-                onsucceed: Some("loop2".to_string()),
-                onfail: Some("loop3".to_string()),
-            },
-            Process {
-                log_monitors: Vec::new(),
-                name: "loop2".to_string(),
-                onsucceed: Some("loop3".to_string()),
-                onfail: None,
-            },
-        ];
-
-        assert_eq!(concurrent_processes().parse(example_1), Err("]"));
-        assert_eq!(concurrent_processes().parse(example_2)?, ("", expected_2));
+    #[test]
+    fn test_process_requires_a_name() {
+        let expected_name = |remaining| {
+            Err(ParseFailure::new(
+                remaining,
+                vec![Expected::Label("process name")],
+            ))
+        };
+        let expected_handler = |exit_code| {
+            let mut expected = vec![
+                Expected::Literal("["),
+                Expected::Literal("{"),
+                Expected::Label("process name"),
+            ];
+
+            if exit_code {
+                expected.insert(0, Expected::Label("exit code"));
+            }
+
+            Err(ParseFailure::new(";", expected))
+        };
+
+        assert_eq!(single_process().parse(";"), expected_name(";"));
+        assert_eq!(single_process().parse("? : ;"), expected_name("? : ;"));
+        assert_eq!(single_process().parse("loop1 ? ;"), expected_handler(true));
+        assert_eq!(single_process().parse("loop1 : ;"), expected_handler(false));
+    }
+
+    #[test]
+    fn test_process_with_quoted_names() -> Result<(), String> {
+        let example = r#""build:web" ? "db migrate" : "notify \"ops\""; @"web:errors""#;
+
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
+            log_monitors: vec![Spanned::new("web:errors".to_string(), Span::new(48, 60))],
+            name: Spanned::new("build:web".to_string(), Span::new(0, 11)),
+            onsucceed: Some(Handler::Process(
+                Spanned::new("db migrate".to_string(), Span::new(14, 26)),
+                Vec::new(),
+            )),
+            onfail: Some(Handler::Process(
+                Spanned::new("notify \"ops\"".to_string(), Span::new(29, 45)),
+                Vec::new(),
+            )),
+            ontimeout: None,
+            retry: None,
+            span: Span::new(0, 60),
+            timeout: None,
+        };
+
+        assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
     }
 
     #[test]
-    fn test_process_with_log_monitors() -> Result<(), String> {
-        let example = "loop1; @foo @bar @baz";
+    fn test_quoted_process_name_cannot_be_empty() {
+        assert_eq!(
+            single_process().parse(r#"loop1 ? "";"#),
+            Err(ParseFailure {
+                expected: vec![
+                    Expected::Label("exit code"),
+                    Expected::Literal("["),
+                    Expected::Literal("{")
+                ],
+                ..ParseFailure::rejected(
+                    r#""";"#,
+                    Rejection::new(Span::new(8, 10), "process names cannot be empty")
+                )
+            })
+        );
+    }
 
-        let expected = vec![Process {
-            log_monitors: vec!["foo".to_string(), "bar".to_string(), "baz".to_string()],
-            name: "loop1".to_string(),
-            onsucceed: None,
-            onfail: None,
-        }];
+    #[test]
+    fn test_format_name_round_trips() -> Result<(), String> {
+        for name in [
+            "loop1",
+            "build:web",
+            "db migrate",
+            "1st",
+            "say \"hi\"",
+            "a\\b\n",
+            "日本語",
+            "a\u{301}",
+        ] {
+            let example = format!("{};", format_name(name));
+
+            assert_eq!(single_process().parse(&example)?.output.name.value, name);
+        }
+
+        assert_eq!(format_name("loop-1_a"), "loop-1_a");
+        assert_eq!(format_name("build:web"), r#""build:web""#);
+        assert_eq!(format_name("a\u{301}"), "\"a\u{301}\"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_args() -> Result<(), String> {
+        let example = r#"deploy(env="staging", replicas=3) ? notify; @errors"#;
+
+        let process = single_process().parse(example)?.output;
+        let args: Vec<(&str, String)> = process
+            .args
+            .iter()
+            .map(|arg| (arg.name.value.as_str(), arg.value.value.to_string()))
+            .collect();
+
+        assert_eq!(process.name, spanned("deploy", 0));
+        assert_eq!(
+            args,
+            vec![
+                ("env", r#""staging""#.to_string()),
+                ("replicas", "3".to_string())
+            ]
+        );
+        assert_eq!(
+            process.onsucceed,
+            Some(Handler::Process(spanned("notify", 36), Vec::new()))
+        );
+        assert_eq!(process.log_monitors, vec![spanned("errors", 45)]);
+        assert_eq!(process.span, Span::new(0, 51));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_retry() -> Result<(), String> {
+        let example = "flaky-test retry(3, backoff=2s) ? report : alert;";
+
+        let expected = Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
+            log_monitors: Vec::new(),
+            name: spanned("flaky-test", 0),
+            onsucceed: Some(Handler::Process(spanned("report", 34), Vec::new())),
+            onfail: Some(Handler::Process(spanned("alert", 43), Vec::new())),
+            ontimeout: None,
+            retry: Some(Spanned::new(
+                Retry {
+                    count: 3,
+                    backoff: Some(Backoff::Fixed(Duration::from_secs(2))),
+                    jitter: None,
+                },
+                Span::new(11, 31),
+            )),
+            span: Span::new(0, 49),
+            timeout: None,
+        };
 
         assert_eq!(single_process().parse(example)?, ("", expected));
         Ok(())
     }
 
     #[test]
-    fn test_concurrent_processes_with_log_monitors() -> Result<(), String> {
-        let example = r#"
-            [
-                loop1 ? loop2 : loop3; @foo @bar
-                loop2 ? loop3 : loop4;
-                loop3 ? loop4 : loop5; @baz
+    fn test_process_with_timeout() -> Result<(), String> {
+        let example = "migrate timeout=1h30m retry(2) ? serve : rollback ~ page-oncall;";
+
+        let process = single_process().parse(example)?.output;
+
+        assert_eq!(
+            process.timeout,
+            Some(Spanned::new(Duration::from_secs(5400), Span::new(8, 21)))
+        );
+        assert_eq!(
+            process.retry.map(|retry| retry.span),
+            Some(Span::new(22, 30))
+        );
+        assert_eq!(
+            process.onsucceed,
+            Some(Handler::Process(spanned("serve", 33), Vec::new()))
+        );
+        assert_eq!(
+            process.onfail,
+            Some(Handler::Process(spanned("rollback", 41), Vec::new()))
+        );
+        assert_eq!(
+            process.ontimeout,
+            Some(Handler::Process(spanned("page-oncall", 52), Vec::new()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_handler_blocks() -> Result<(), String> {
+        let example = "build ? [ test; lint; ] : { notify; cleanup; };";
+
+        let process = single_process().parse(example)?.output;
+
+        assert!(matches!(
+            &process.onsucceed,
+            Some(Handler::Concurrent(group, _)) if group.tasks.len() == 2
+        ));
+        assert!(matches!(
+            &process.onfail,
+            Some(Handler::Sequential(group)) if group.span == Span::new(26, 46)
+        ));
+        assert_eq!(process.span, Span::new(0, 47));
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_exit_code_branches() -> Result<(), String> {
+        let example = "sync ?0 next ?2 skip ?3..9 { partial; report; } : fail;";
+
+        let process = single_process().parse(example)?.output;
+        let codes: Vec<ExitCodes> = process
+            .branches
+            .iter()
+            .map(|branch| branch.codes.value)
+            .collect();
+
+        assert_eq!(
+            codes,
+            vec![
+                ExitCodes::Code(0),
+                ExitCodes::Code(2),
+                ExitCodes::Range(3, 9)
             ]
-        "#;
+        );
+        assert!(matches!(process.branches[2].target, Handler::Sequential(_)));
+        assert_eq!(process.onsucceed, None);
+        assert_eq!(
+            process.onfail,
+            Some(Handler::Process(spanned("fail", 50), Vec::new()))
+        );
+        Ok(())
+    }
 
-        let expected = vec![
-            Process {
-                log_monitors: vec!["foo".to_string(), "bar".to_string()],
-                name: "loop1".to_string(),
-                onsucceed: Some("loop2".to_string()),
-                onfail: Some("loop3".to_string()),
-            },
-            Process {
-                log_monitors: Vec::new(),
-                name: "loop2".to_string(),
-                onsucceed: Some("loop3".to_string()),
-                onfail: Some("loop4".to_string()),
-            },
-            Process {
-                log_monitors: vec!["baz".to_string()],
-                name: "loop3".to_string(),
-                onsucceed: Some("loop4".to_string()),
-                onfail: Some("loop5".to_string()),
-            },
-        ];
-
-        assert_eq!(concurrent_processes().parse(example)?, ("", expected));
+    #[test]
+    fn test_process_with_always_handler() -> Result<(), String> {
+        let example = "e2e timeout=5m ? report : alert ~ page ! { stop-db; stop-web; };";
+
+        let process = single_process().parse(example)?.output;
+
+        assert_eq!(
+            process.ontimeout,
+            Some(Handler::Process(spanned("page", 34), Vec::new()))
+        );
+        assert!(matches!(
+            process.always,
+            Some(Handler::Sequential(group)) if group.span == Span::new(41, 63)
+        ));
+        assert_eq!(
+            single_process().parse("teardown ! cleanup;")?.output.always,
+            Some(Handler::Process(spanned("cleanup", 11), Vec::new()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_condition() -> Result<(), String> {
+        let example = r#"deploy; when branch == "main"; @errors next;"#;
+
+        let parsed = single_process().parse(example)?;
+        let condition = parsed.output.condition.ok_or("expected a condition")?;
+
+        assert_eq!(parsed.remaining.as_str(), "next;");
+        assert_eq!(condition.span, Span::new(13, 29));
+        assert_eq!(
+            condition.value,
+            Condition::Equal(
+                Operand::Variable(spanned("branch", 13)),
+                Operand::String(Spanned::new("main".to_string(), Span::new(23, 29))),
+            )
+        );
+        assert_eq!(parsed.output.span, Span::new(0, 38));
+        assert_eq!(
+            single_process()
+                .parse(r#"deploy; when a == "b" { c; }"#)?
+                .output
+                .condition,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_space_before_semicolon() -> Result<(), String> {
+        for example in ["a ;", "a /* c */ ;", "a ? b ;", "a(x=1) # c\n;"] {
+            let parsed = single_process().parse(example)?;
+
+            assert_eq!(parsed.remaining.as_str(), "");
+            assert_eq!(parsed.output.span, Span::new(0, example.len()));
+        }
+
+        let failure = single_process().parse("a /* c */ ]").unwrap_err();
+
+        assert_eq!(failure.remaining, "]");
+        assert!(failure.expected.contains(&Expected::Literal("(")));
+        assert!(failure.expected.contains(&Expected::Literal(";")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_handlers_in_order() -> Result<(), String> {
+        let mut process = single_process()
+            .parse("a timeout=1s ?1 b ? c : d ~ e ! f;")?
+            .output;
+
+        let names = |process: &Process| {
+            process
+                .handlers()
+                .map(|handler| match handler {
+                    Handler::Process(name, _) => name.value.clone(),
+                    _ => String::new(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(&process), vec!["b", "c", "d", "e", "f"]);
+        assert_eq!(process.handlers_mut().count(), 5);
         Ok(())
     }
+
+    #[test]
+    fn test_modifiers_are_given_once() {
+        assert_eq!(
+            single_process().parse("loop1 timeout=1s timeout=2s;"),
+            Err(ParseFailure {
+                expected: vec![Expected::Literal("retry"), Expected::Literal("timeout")],
+                ..ParseFailure::rejected(
+                    ";",
+                    Rejection::new(Span::new(17, 27), "this modifier is already given")
+                )
+            })
+        );
+    }
+
+    #[test]
+    fn test_ontimeout_needs_a_timeout() {
+        assert_eq!(
+            single_process().parse("loop1 : loop2 ~ loop3;"),
+            Err(ParseFailure {
+                expected: vec![Expected::Literal("("), Expected::Literal("!")],
+                ..ParseFailure::rejected(
+                    ";",
+                    Rejection::new(
+                        Span::new(16, 21),
+                        "this handler never runs because the process has no timeout"
+                    )
+                    .with_note("add a modifier such as `timeout=10m` after the process name")
+                )
+            })
+        );
+    }
 }
@@ -0,0 +1,182 @@
+use std::mem;
+use std::time::Duration;
+
+use crate::generic::builtin_matchers::duration::duration;
+use crate::generic::builtin_matchers::integer::unsigned;
+use crate::prelude::*;
+
+/// How often to rerun a failed process before its `onfail` handler runs, as
+/// in `flaky-test retry(3, backoff=2s);`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retry {
+    /// How many times a failed process is rerun, not counting its first
+    /// run: `retry(3)` runs it at most four times, and `retry(0)` only once.
+    pub count: u32,
+    pub backoff: Option<Backoff>,
+    /// The upper bound of a random delay added to each backoff.
+    pub jitter: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backoff {
+    /// Waits the same time before every attempt: `backoff=2s`.
+    Fixed(Duration),
+    /// Doubles the wait after every attempt: `backoff=exponential(2s)`.
+    Exponential(Duration),
+}
+
+enum RetryOption {
+    Backoff(Backoff),
+    Jitter(Duration),
+}
+
+/// Parses `retry(count)` followed by optional `backoff=` and `jitter=`
+/// settings, each of which may be given once.
+pub fn retry<'a>() -> impl Parser<'a, Retry> {
+    let options = n(
+        right(
+            whitespace_wrap(literal(",")),
+            whitespace_wrap(retry_option().spanned()),
+        ),
+        0..,
+    );
+
+    right(
+        pair(
+            whitespace_wrap(literal("retry")),
+            whitespace_wrap(literal("(")),
+        ),
+        left(
+            pair(whitespace_wrap(unsigned("retry count").spanned()), options),
+            literal(")"),
+        ),
+    )
+    .validate(|(_, options)| {
+        reject_duplicates(
+            options,
+            |option| Spanned::new(mem::discriminant(&option.value), option.span),
+            |_| "this setting is already given".to_string(),
+        )
+    })
+    .map(|(count, options)| {
+        let mut retry = Retry {
+            count: count.value,
+            backoff: None,
+            jitter: None,
+        };
+
+        for option in options {
+            match option.value {
+                RetryOption::Backoff(backoff) => retry.backoff = Some(backoff),
+                RetryOption::Jitter(jitter) => retry.jitter = Some(jitter),
+            }
+        }
+
+        retry
+    })
+}
+
+fn retry_option<'a>() -> impl Parser<'a, RetryOption> {
+    either(
+        right(
+            pair(literal("backoff"), whitespace_wrap(literal("="))),
+            backoff(),
+        )
+        .map(RetryOption::Backoff),
+        right(
+            pair(literal("jitter"), whitespace_wrap(literal("="))),
+            duration,
+        )
+        .map(RetryOption::Jitter),
+    )
+}
+
+fn backoff<'a>() -> impl Parser<'a, Backoff> {
+    either(
+        duration.map(Backoff::Fixed),
+        right(
+            pair(literal("exponential"), whitespace_wrap(literal("("))),
+            left(whitespace_wrap(duration), literal(")")),
+        )
+        .map(Backoff::Exponential),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
+
+    #[test]
+    fn test_retry() -> Result<(), String> {
+        assert_eq!(
+            retry().parse("retry(3)")?,
+            (
+                "",
+                Retry {
+                    count: 3,
+                    backoff: None,
+                    jitter: None,
+                }
+            )
+        );
+        assert_eq!(
+            retry().parse("retry( 3, backoff = 2s )")?,
+            (
+                "",
+                Retry {
+                    count: 3,
+                    backoff: Some(Backoff::Fixed(Duration::from_secs(2))),
+                    jitter: None,
+                }
+            )
+        );
+        assert_eq!(
+            retry().parse("retry(5, jitter=250ms, backoff=exponential(1s)) ?")?,
+            (
+                " ?",
+                Retry {
+                    count: 5,
+                    backoff: Some(Backoff::Exponential(Duration::from_secs(1))),
+                    jitter: Some(Duration::from_millis(250)),
+                }
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_needs_a_count() -> Result<(), String> {
+        assert_eq!(
+            retry().parse("retry(backoff=2s)"),
+            Err(ParseFailure::new(
+                "backoff=2s)",
+                vec![Expected::Label("retry count")]
+            ))
+        );
+        assert_eq!(retry().parse("retry(0)")?.output.count, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_settings_are_given_once() {
+        assert_eq!(
+            retry().parse("retry(2, jitter=1s, jitter=2s)"),
+            Err(ParseFailure::rejected(
+                "",
+                Rejection::new(Span::new(20, 29), "this setting is already given")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_unknown_retry_setting() {
+        assert_eq!(
+            retry().parse("retry(2, delay=1s)"),
+            Err(ParseFailure::new(
+                "delay=1s)",
+                vec![Expected::Literal("backoff"), Expected::Literal("jitter")]
+            ))
+        );
+    }
+}
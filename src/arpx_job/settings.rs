@@ -0,0 +1,273 @@
+use std::mem;
+
+use crate::generic::builtin_matchers::integer::positive_count;
+use crate::prelude::*;
+
+/// What a concurrent block does when one of its tasks fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Stops the other tasks as soon as one fails: `policy=fail-fast`.
+    FailFast,
+    /// Lets the other tasks finish before the block fails: `policy=wait-all`.
+    WaitAll,
+}
+
+/// Which tasks of a concurrent block have to finish before the block does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Waits for every task.
+    #[default]
+    All,
+    /// Takes the first task to finish, whatever its outcome, and cancels
+    /// the rest: `[any| ...]`.
+    Any,
+    /// Takes the first task to succeed and cancels the rest, failing only
+    /// if every task fails: `[first-success| ...]`.
+    FirstSuccess,
+}
+
+/// How the tasks of a concurrent block run, as given by the settings at its
+/// start.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Concurrency {
+    /// Which tasks have to finish, as in `[first-success| ...]`.
+    pub mode: Mode,
+    /// How many tasks may run at once, as in `[max=4; ...]`. Without a
+    /// limit, every task starts at once.
+    pub limit: Option<u32>,
+    /// What the block does when one of its tasks fails, as in
+    /// `[policy=wait-all; ...]`.
+    pub policy: Option<FailurePolicy>,
+}
+
+impl From<Settings> for Concurrency {
+    fn from(settings: Settings) -> Self {
+        Concurrency {
+            mode: settings.mode.unwrap_or_default(),
+            limit: settings.limit,
+            policy: settings.policy,
+        }
+    }
+}
+
+/// Settings given at the start of a concurrent block, either in full as in
+/// `[max=4, policy=wait-all; ...]` or as just a limit or mode, as in
+/// `[4| ...]` or `[any| ...]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Settings {
+    pub limit: Option<u32>,
+    pub mode: Option<Mode>,
+    pub policy: Option<FailurePolicy>,
+}
+
+enum Setting {
+    Max(u32),
+    Mode(Mode),
+    Policy(FailurePolicy),
+}
+
+pub(crate) fn settings<'a>() -> impl Parser<'a, Settings> {
+    let short = left(
+        whitespace_wrap(either(
+            limit().map(|limit| Settings {
+                limit: Some(limit),
+                ..Settings::default()
+            }),
+            mode().map(|mode| Settings {
+                mode: Some(mode),
+                ..Settings::default()
+            }),
+        )),
+        literal("|"),
+    );
+
+    let full = left(
+        pair(
+            whitespace_wrap(setting().spanned()),
+            n(
+                right(literal(","), whitespace_wrap(setting().spanned())),
+                0..,
+            ),
+        ),
+        literal(";"),
+    )
+    .map(|(first, mut rest)| {
+        rest.insert(0, first);
+        rest
+    })
+    .validate(|settings| {
+        reject_duplicates(
+            settings,
+            |setting| Spanned::new(mem::discriminant(&setting.value), setting.span),
+            |_| "this setting is already given".to_string(),
+        )?;
+
+        let racing = settings
+            .iter()
+            .any(|setting| matches!(setting.value, Setting::Mode(Mode::Any | Mode::FirstSuccess)));
+        let policy = settings
+            .iter()
+            .find(|setting| matches!(setting.value, Setting::Policy(_)));
+
+        match policy {
+            Some(policy) if racing => Err(Rejection::new(
+                policy.span,
+                "a failure policy only applies to blocks that wait for every task",
+            )
+            .with_note(
+                "`any` and `first-success` blocks cancel their other tasks once one finishes",
+            )),
+            _ => Ok(()),
+        }
+    })
+    .map(|settings| {
+        let mut result = Settings::default();
+
+        for setting in settings {
+            match setting.value {
+                Setting::Max(limit) => result.limit = Some(limit),
+                Setting::Mode(mode) => result.mode = Some(mode),
+                Setting::Policy(policy) => result.policy = Some(policy),
+            }
+        }
+
+        result
+    });
+
+    either(short, full)
+}
+
+/// Parses no settings, for blocks that do not take any.
+pub(crate) fn no_settings(input: Input<'_>) -> ParseResult<'_, Settings> {
+    Ok(Parsed::new(input, Settings::default()))
+}
+
+fn setting<'a>() -> impl Parser<'a, Setting> {
+    either(
+        either(
+            right(pair(literal("max"), whitespace_wrap(literal("="))), limit()).map(Setting::Max),
+            right(pair(literal("mode"), whitespace_wrap(literal("="))), mode()).map(Setting::Mode),
+        ),
+        right(
+            pair(literal("policy"), whitespace_wrap(literal("="))),
+            either(
+                literal("fail-fast").map(|()| FailurePolicy::FailFast),
+                literal("wait-all").map(|()| FailurePolicy::WaitAll),
+            ),
+        )
+        .map(Setting::Policy),
+    )
+}
+
+fn mode<'a>() -> impl Parser<'a, Mode> {
+    either(
+        either(
+            literal("all").map(|()| Mode::All),
+            literal("any").map(|()| Mode::Any),
+        ),
+        literal("first-success").map(|()| Mode::FirstSuccess),
+    )
+}
+
+fn limit<'a>() -> impl Parser<'a, u32> {
+    positive_count(
+        "concurrency limit",
+        "a block must run at least one task at a time",
+        "leave out the limit to run every task at once",
+    )
+    .map(|limit| limit.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::ParseFailure;
+
+    #[test]
+    fn test_settings() -> Result<(), String> {
+        assert_eq!(
+            settings().parse("4| a;")?,
+            (
+                " a;",
+                Settings {
+                    limit: Some(4),
+                    mode: None,
+                    policy: None,
+                }
+            )
+        );
+        assert_eq!(
+            settings().parse("max = 4, policy=wait-all; a;")?,
+            (
+                " a;",
+                Settings {
+                    limit: Some(4),
+                    mode: None,
+                    policy: Some(FailurePolicy::WaitAll),
+                }
+            )
+        );
+        assert_eq!(
+            settings().parse("policy=fail-fast;")?.output,
+            Settings {
+                limit: None,
+                mode: None,
+                policy: Some(FailurePolicy::FailFast),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode_settings() -> Result<(), String> {
+        assert_eq!(settings().parse("any|")?.output.mode, Some(Mode::Any));
+        assert_eq!(
+            settings().parse("max=2, mode=first-success;")?.output,
+            Settings {
+                limit: Some(2),
+                mode: Some(Mode::FirstSuccess),
+                policy: None,
+            }
+        );
+        assert_eq!(
+            settings().parse("mode=any, policy=wait-all;"),
+            Err(ParseFailure::rejected(
+                "",
+                Rejection::new(
+                    Span::new(10, 25),
+                    "a failure policy only applies to blocks that wait for every task"
+                )
+                .with_note(
+                    "`any` and `first-success` blocks cancel their other tasks once one finishes"
+                )
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_are_given_once() {
+        assert_eq!(
+            settings().parse("max=2, max=3;"),
+            Err(ParseFailure::rejected(
+                "",
+                Rejection::new(Span::new(7, 12), "this setting is already given")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_limit_must_be_positive() {
+        assert_eq!(
+            settings().parse("0|"),
+            Err(ParseFailure::rejected(
+                "|",
+                Rejection::new(
+                    Span::new(0, 1),
+                    "a block must run at least one task at a time"
+                )
+                .with_note("leave out the limit to run every task at once")
+            ))
+        );
+    }
+}
@@ -1,20 +1,147 @@
-use crate::arpx_job::process::{concurrent_processes, single_process, Process};
+use crate::arpx_job::condition::{when, When};
+use crate::arpx_job::include::{include, Include};
+use crate::arpx_job::loops::{forever, repeat, Forever, Repeat};
+use crate::arpx_job::macros::Invocation;
+use crate::arpx_job::process::{single_process, Process};
+use crate::arpx_job::settings::{no_settings, settings, Concurrency};
+use crate::generic::combinators::lazy::lazy;
 use crate::prelude::*;
 
+/// A unit of work in a job: a single process, a block of tasks that run
+/// concurrently (`[ ... ]`) or one after another (`{ ... }`), or the tasks of
+/// an included script or a macro call, or a loop over or condition on a
+/// block. Blocks nest to any depth, so `[ { a; b; } c; ]` runs
+/// `a` then `b` alongside `c`.
+// Processes are by far the most common task, so they are stored inline
+// rather than paying an allocation each to make the enum smaller.
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Task {
-    pub processes: Vec<Process>,
+pub enum Task {
+    Process(Process),
+    Concurrent(Group, Concurrency),
+    Sequential(Group),
+    Include(Include),
+    Invocation(Invocation),
+    Repeat(Repeat),
+    Forever(Forever),
+    When(When),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub tasks: Vec<Task>,
+    pub span: Span,
+}
+
+impl Task {
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Task::Process(process) => process.span,
+            Task::Concurrent(group, _) | Task::Sequential(group) => group.span,
+            Task::Include(include) => include.span,
+            Task::Invocation(invocation) => invocation.span,
+            Task::Repeat(repeat) => repeat.span,
+            Task::Forever(forever) => forever.span,
+            Task::When(when) => when.span,
+        }
+    }
+
+    /// Returns every process in this task in source order, however deeply
+    /// nested. Processes in handler blocks are not included, since they only
+    /// run in response to another process.
+    #[must_use]
+    pub fn processes(&self) -> Vec<&Process> {
+        match self {
+            Task::Process(process) => vec![process],
+            Task::Concurrent(group, _) | Task::Sequential(group) => {
+                group.tasks.iter().flat_map(Task::processes).collect()
+            }
+            Task::Include(include) => include.tasks.iter().flat_map(Task::processes).collect(),
+            Task::Invocation(invocation) => {
+                invocation.tasks.iter().flat_map(Task::processes).collect()
+            }
+            Task::Repeat(repeat) => repeat.tasks.iter().flat_map(Task::processes).collect(),
+            Task::Forever(forever) => forever.tasks.iter().flat_map(Task::processes).collect(),
+            Task::When(when) => when.tasks.iter().flat_map(Task::processes).collect(),
+        }
+    }
 }
 
 #[must_use]
 pub fn task<'a>() -> impl Parser<'a, Task> {
-    either(concurrent_processes(), single_process()).map(|processes| Task { processes })
+    either(
+        either(
+            concurrent().map(|(group, concurrency)| Task::Concurrent(group, concurrency)),
+            sequential().map(Task::Sequential),
+        ),
+        either(
+            either(
+                either(repeat().map(Task::Repeat), forever().map(Task::Forever)),
+                when().map(Task::When),
+            ),
+            either(
+                include().map(Task::Include),
+                single_process().map(Task::Process),
+            ),
+        ),
+    )
+}
+
+/// Parses a block of tasks that run concurrently: `[ ... ]`, optionally
+/// starting with settings such as `[max=4; ...]` or `[any| ...]`.
+pub(crate) fn concurrent<'a>() -> impl Parser<'a, (Group, Concurrency)> {
+    group("[", optional(settings()), "]")
+        .map(|(settings, group)| (group, settings.unwrap_or_default().into()))
+}
+
+/// Parses a block of tasks that run one after another: `{ ... }`.
+pub(crate) fn sequential<'a>() -> impl Parser<'a, Group> {
+    group("{", no_settings, "}").map(|(_, group)| group)
+}
+
+/// Parses a block of tasks between `open` and `close`, along with whatever
+/// `header` parses after `open`. Blocks with fewer than two tasks are
+/// accepted here and reported by `lint` instead, since generated scripts
+/// often contain them.
+fn group<'a, H, S>(
+    open: &'static str,
+    header: H,
+    close: &'static str,
+) -> impl Parser<'a, (S, Group)>
+where
+    H: 'a + Parser<'a, S>,
+    S: 'a,
+{
+    let tasks = right(literal(open), pair(header, n(lazy(task), 0..)));
+
+    whitespace_wrap(left(tasks, right(space0(), literal(close))).spanned().map(
+        |Spanned { value, span }| {
+            let (header, tasks) = value;
+
+            (header, Group { tasks, span })
+        },
+    ))
+}
+
+/// Returns the names of the processes in `tasks`, as `Task::processes`
+/// finds them, for tests to compare.
+#[cfg(test)]
+pub(crate) fn process_names<'t>(tasks: impl IntoIterator<Item = &'t Task>) -> Vec<&'t str> {
+    tasks
+        .into_iter()
+        .flat_map(Task::processes)
+        .map(|process| process.name.value.as_str())
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arpx_job::handler::Handler;
     use crate::arpx_job::process::Process;
+    use crate::arpx_job::settings::{FailurePolicy, Mode};
+    use crate::generic::span::spanned;
 
     #[test]
     fn test_single_task() -> Result<(), String> {
@@ -22,14 +149,20 @@ mod tests {
             loop1 ? loop2 : loop3;
         "#;
 
-        let expected = Task {
-            processes: vec![Process {
-                log_monitors: Vec::new(),
-                name: "loop1".to_string(),
-                onsucceed: Some("loop2".to_string()),
-                onfail: Some("loop3".to_string()),
-            }],
-        };
+        let expected = Task::Process(Process {
+            always: None,
+            args: Vec::new(),
+            branches: Vec::new(),
+            condition: None,
+            log_monitors: Vec::new(),
+            name: spanned("loop1", 13),
+            onsucceed: Some(Handler::Process(spanned("loop2", 21), Vec::new())),
+            onfail: Some(Handler::Process(spanned("loop3", 29), Vec::new())),
+            ontimeout: None,
+            retry: None,
+            span: Span::new(13, 35),
+            timeout: None,
+        });
 
         assert_eq!(task().parse(example)?, ("", expected));
         Ok(())
@@ -44,24 +177,372 @@ mod tests {
             ]
         "#;
 
-        let expected = Task {
-            processes: vec![
-                Process {
-                    log_monitors: Vec::new(),
-                    name: "loop1".to_string(),
-                    onsucceed: Some("loop2".to_string()),
-                    onfail: Some("loop3".to_string()),
-                },
-                Process {
-                    log_monitors: Vec::new(),
-                    name: "loop2".to_string(),
-                    onsucceed: Some("loop3".to_string()),
-                    onfail: Some("loop4".to_string()),
-                },
-            ],
-        };
+        let expected = Task::Concurrent(
+            Group {
+                tasks: vec![
+                    Task::Process(Process {
+                        always: None,
+                        args: Vec::new(),
+                        branches: Vec::new(),
+                        condition: None,
+                        log_monitors: Vec::new(),
+                        name: spanned("loop1", 31),
+                        onsucceed: Some(Handler::Process(spanned("loop2", 39), Vec::new())),
+                        onfail: Some(Handler::Process(spanned("loop3", 47), Vec::new())),
+                        ontimeout: None,
+                        retry: None,
+                        span: Span::new(31, 53),
+                        timeout: None,
+                    }),
+                    Task::Process(Process {
+                        always: None,
+                        args: Vec::new(),
+                        branches: Vec::new(),
+                        condition: None,
+                        log_monitors: Vec::new(),
+                        name: spanned("loop2", 70),
+                        onsucceed: Some(Handler::Process(spanned("loop3", 78), Vec::new())),
+                        onfail: Some(Handler::Process(spanned("loop4", 86), Vec::new())),
+                        ontimeout: None,
+                        retry: None,
+                        span: Span::new(70, 92),
+                        timeout: None,
+                    }),
+                ],
+                span: Span::new(13, 106),
+            },
+            Concurrency::default(),
+        );
 
         assert_eq!(task().parse(example)?, ("", expected));
         Ok(())
     }
+
+    #[test]
+    fn test_concurrent_group() -> Result<(), String> {
+        let example = r#"
+            [
+                loop1 ? loop2 : loop3;
+                loop2 ? loop3 : loop4;
+                loop3 ? loop4 : loop5;
+            ]
+        "#;
+
+        let expected = vec![
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: Vec::new(),
+                name: spanned("loop1", 31),
+                onsucceed: Some(Handler::Process(spanned("loop2", 39), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop3", 47), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(31, 53),
+                timeout: None,
+            }),
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: Vec::new(),
+                name: spanned("loop2", 70),
+                onsucceed: Some(Handler::Process(spanned("loop3", 78), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop4", 86), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(70, 92),
+                timeout: None,
+            }),
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: Vec::new(),
+                name: spanned("loop3", 109),
+                onsucceed: Some(Handler::Process(spanned("loop4", 117), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop5", 125), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(109, 131),
+                timeout: None,
+            }),
+        ];
+
+        assert_eq!(
+            task().parse(example)?,
+            (
+                "",
+                Task::Concurrent(
+                    Group {
+                        tasks: expected,
+                        span: Span::new(13, 145),
+                    },
+                    Concurrency::default()
+                )
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_group_with_one_or_two_tasks() -> Result<(), String> {
+        let example_1 = "[loop1 ? loop2 : loop3;]";
+        let example_2 = "[loop1 ? loop2 : loop3; loop2 ? loop3;]";
+
+        let expected_2 = vec![
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: Vec::new(),
+                name: spanned("loop1", 1),
+                onsucceed: Some(Handler::Process(spanned("loop2", 9), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop3", 17), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(1, 23),
+                timeout: None,
+            }),
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: Vec::new(),
+                name: spanned("loop2", 24),
+                onsucceed: Some(Handler::Process(spanned("loop3", 32), Vec::new())),
+                onfail: None,
+                ontimeout: None,
+                retry: None,
+                span: Span::new(24, 38),
+                timeout: None,
+            }),
+        ];
+
+        assert_eq!(
+            task().parse(example_1)?,
+            (
+                "",
+                Task::Concurrent(
+                    Group {
+                        tasks: expected_2[..1].to_vec(),
+                        span: Span::new(0, 24),
+                    },
+                    Concurrency::default()
+                )
+            )
+        );
+        assert_eq!(
+            task().parse(example_2)?,
+            (
+                "",
+                Task::Concurrent(
+                    Group {
+                        tasks: expected_2,
+                        span: Span::new(0, 39),
+                    },
+                    Concurrency::default()
+                )
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_group_with_log_monitors() -> Result<(), String> {
+        let example = r#"
+            [
+                loop1 ? loop2 : loop3; @foo @bar
+                loop2 ? loop3 : loop4;
+                loop3 ? loop4 : loop5; @baz
+            ]
+        "#;
+
+        let expected = vec![
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: vec![spanned("foo", 55), spanned("bar", 60)],
+                name: spanned("loop1", 31),
+                onsucceed: Some(Handler::Process(spanned("loop2", 39), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop3", 47), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(31, 63),
+                timeout: None,
+            }),
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: Vec::new(),
+                name: spanned("loop2", 80),
+                onsucceed: Some(Handler::Process(spanned("loop3", 88), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop4", 96), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(80, 102),
+                timeout: None,
+            }),
+            Task::Process(Process {
+                always: None,
+                args: Vec::new(),
+                branches: Vec::new(),
+                condition: None,
+                log_monitors: vec![spanned("baz", 143)],
+                name: spanned("loop3", 119),
+                onsucceed: Some(Handler::Process(spanned("loop4", 127), Vec::new())),
+                onfail: Some(Handler::Process(spanned("loop5", 135), Vec::new())),
+                ontimeout: None,
+                retry: None,
+                span: Span::new(119, 146),
+                timeout: None,
+            }),
+        ];
+
+        assert_eq!(
+            task().parse(example)?,
+            (
+                "",
+                Task::Concurrent(
+                    Group {
+                        tasks: expected,
+                        span: Span::new(13, 160),
+                    },
+                    Concurrency::default()
+                )
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequential_block_in_concurrent_group() -> Result<(), String> {
+        let example = "[ { build; test; } lint; ]";
+
+        let task = task().parse(example)?.output;
+
+        let Task::Concurrent(group, _) = &task else {
+            return Err(format!("expected a concurrent group, found {:?}", task));
+        };
+
+        assert_eq!(group.span, Span::new(0, 26));
+        assert!(matches!(
+            &group.tasks[..],
+            [Task::Sequential(block), Task::Process(_)] if block.span == Span::new(2, 18)
+        ));
+        assert_eq!(process_names([&task]), vec!["build", "test", "lint"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_group_in_sequential_block() -> Result<(), String> {
+        let example = "{ [ web; api; ] deploy; }";
+
+        let task = task().parse(example)?.output;
+
+        assert!(matches!(
+            &task,
+            Task::Sequential(Group { tasks, .. })
+                if matches!(&tasks[..], [Task::Concurrent(..), Task::Process(_)])
+        ));
+        assert_eq!(process_names([&task]), vec!["web", "api", "deploy"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deeply_nested_blocks() -> Result<(), String> {
+        let example = "[ { a; [ b; { c; d; } ] } e; ]";
+
+        let task = task().parse(example)?.output;
+
+        assert_eq!(process_names([&task]), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(task.span(), Span::new(0, 30));
+        Ok(())
+    }
+
+    #[test]
+    fn test_small_blocks() -> Result<(), String> {
+        assert_eq!(
+            process_names([&task().parse("{ loop1; }")?.output]),
+            vec!["loop1"]
+        );
+        assert!(matches!(
+            task().parse("[ ]")?.output,
+            Task::Concurrent(Group { tasks, span }, _)
+                if tasks.is_empty() && span == Span::new(0, 3)
+        ));
+        assert!(matches!(
+            task().parse("{}")?.output,
+            Task::Sequential(Group { tasks, .. }) if tasks.is_empty()
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_block_settings() -> Result<(), String> {
+        let example = "[max=2, policy=wait-all; shard1; shard2; shard3; ]";
+
+        let Task::Concurrent(group, concurrency) = task().parse(example)?.output else {
+            return Err("expected a concurrent group".to_string());
+        };
+
+        assert_eq!(concurrency.limit, Some(2));
+        assert_eq!(concurrency.policy, Some(FailurePolicy::WaitAll));
+        assert_eq!(group.tasks.len(), 3);
+
+        let Task::Concurrent(_, concurrency) = task().parse("[ 4 | a; b; ]")?.output else {
+            return Err("expected a concurrent group".to_string());
+        };
+
+        assert_eq!(
+            concurrency,
+            Concurrency {
+                mode: Mode::All,
+                limit: Some(4),
+                policy: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_race_blocks() -> Result<(), String> {
+        let modes: Vec<Mode> = [
+            "[first-success| mirror-a; mirror-b; ]",
+            "[mode=any, max=1; probe-a; probe-b; ]",
+            "[all| a; b; ]",
+        ]
+        .into_iter()
+        .map(|example| match task().parse(example)?.output {
+            Task::Concurrent(_, concurrency) => Ok(concurrency.mode),
+            other => Err(format!("expected a concurrent group, found {:?}", other)),
+        })
+        .collect::<Result<_, String>>()?;
+
+        assert_eq!(modes, vec![Mode::FirstSuccess, Mode::Any, Mode::All]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_processes_named_like_settings() -> Result<(), String> {
+        let named = task().parse("[max; policy; ]")?.output;
+
+        assert_eq!(process_names([&named]), vec!["max", "policy"]);
+        assert!(matches!(
+            named,
+            Task::Concurrent(_, Concurrency { limit: None, .. })
+        ));
+        assert!(task().parse("{max=2; a; b; }").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use crate::arpx_job::args::{Arg, Value};
+use crate::arpx_job::condition::{Condition, Operand};
+use crate::arpx_job::handler::Handler;
+use crate::arpx_job::job::Job;
+use crate::arpx_job::loops::{Forever, Repeat};
+use crate::arpx_job::process::{bare_name, Process};
+use crate::arpx_job::task::Task;
+use crate::generic::builtin_matchers::literal::keyword;
+use crate::generic::builtin_matchers::string::{quoted_segments, quoted_string, Segment};
+use crate::prelude::*;
+
+/// A `let region = "us-east-1";` declaration. Quoted names and string
+/// arguments after it may refer to it as `${region}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variable {
+    pub name: Spanned<String>,
+    pub value: Spanned<String>,
+    pub span: Span,
+}
+
+pub fn variable<'a>() -> impl Parser<'a, Variable> {
+    whitespace_wrap(
+        left(
+            pair(
+                right(
+                    keyword("let"),
+                    whitespace_wrap(bare_name("variable name").spanned()),
+                ),
+                right(
+                    whitespace_wrap(literal("=")),
+                    whitespace_wrap(quoted_string.spanned()),
+                ),
+            ),
+            literal(";"),
+        )
+        .spanned()
+        .map(|Spanned { value, span }| Variable {
+            name: value.0,
+            value: value.1,
+            span,
+        }),
+    )
+}
+
+/// Substitutes variables into the declarations, quoted names, arguments and
+/// conditions of `job`, which was parsed from `source`, and macro arguments
+/// into the tasks of the calls they were passed to. A declaration takes
+/// effect from the end of its `let`, shadowing `vars` and any earlier
+/// declaration of the same name, and a macro's parameters in turn shadow
+/// variables within its body. Returns every reference to a name that is not
+/// defined at that point, as the span of its `${...}` or bare name. Bare
+/// names in conditions that no `let` declares are left for evaluation.
+pub(crate) fn interpolate(
+    job: &mut Job,
+    source: &str,
+    vars: &HashMap<String, String>,
+) -> Vec<Span> {
+    let mut scope = Scope {
+        source,
+        vars,
+        declared: Vec::new(),
+        params: HashMap::new(),
+        undefined: Vec::new(),
+    };
+
+    for variable in &mut job.variables {
+        scope.interpolate(&mut variable.value);
+        scope.declared.push((
+            variable.name.value.clone(),
+            variable.value.value.clone(),
+            variable.span.end,
+        ));
+    }
+
+    for task in &mut job.tasks {
+        scope.task(task);
+    }
+
+    // A macro's body is copied into every call, so a name it cannot resolve
+    // would otherwise be reported once per call.
+    scope.undefined.sort_by_key(|span| span.start);
+    scope.undefined.dedup();
+    scope.undefined
+}
+
+struct Scope<'s> {
+    source: &'s str,
+    vars: &'s HashMap<String, String>,
+    /// Each declaration's name, value and the offset it takes effect at.
+    declared: Vec<(String, String, usize)>,
+    /// The arguments of the macro call whose tasks are being visited.
+    params: HashMap<String, Value>,
+    undefined: Vec<Span>,
+}
+
+impl Scope<'_> {
+    fn task(&mut self, task: &mut Task) {
+        match task {
+            Task::Process(process) => self.process(process),
+            Task::Concurrent(group, _) | Task::Sequential(group) => {
+                for task in &mut group.tasks {
+                    self.task(task);
+                }
+            }
+            Task::Repeat(Repeat { tasks, .. }) | Task::Forever(Forever { tasks, .. }) => {
+                for task in tasks {
+                    self.task(task);
+                }
+            }
+            Task::When(when) => {
+                self.condition(&mut when.condition.value);
+
+                for task in &mut when.tasks {
+                    self.task(task);
+                }
+            }
+            // Included scripts are interpolated on their own when
+            // `load_job_with_vars` loads them.
+            Task::Include(_) => {}
+            Task::Invocation(invocation) => {
+                self.args(&mut invocation.args);
+
+                let params = invocation
+                    .args
+                    .iter()
+                    .map(|arg| (arg.name.value.clone(), arg.value.value.clone()))
+                    .collect();
+                let outer = std::mem::replace(&mut self.params, params);
+
+                for task in &mut invocation.tasks {
+                    self.task(task);
+                }
+
+                self.params = outer;
+            }
+        }
+    }
+
+    fn args(&mut self, args: &mut [Arg]) {
+        for arg in args {
+            match &mut arg.value.value {
+                Value::String(value) => {
+                    let mut spanned = Spanned::new(std::mem::take(value), arg.value.span);
+
+                    self.interpolate(&mut spanned);
+                    *value = spanned.value;
+                }
+                Value::Reference(name) => {
+                    if let Some(value) = self.params.get(name.as_str()) {
+                        arg.value.value = value.clone();
+                    } else if let Some(value) = self.lookup(name, arg.value.span.start) {
+                        arg.value.value = Value::String(value.to_string());
+                    } else {
+                        self.undefined.push(arg.value.span);
+                    }
+                }
+                Value::Number(_) | Value::Boolean(_) => {}
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &mut Condition) {
+        match condition {
+            Condition::Equal(left, right) | Condition::NotEqual(left, right) => {
+                for operand in [left, right] {
+                    match operand {
+                        Operand::String(value) => self.interpolate(value),
+                        Operand::Variable(name) => {
+                            if let Some(value) = self.declared(&name.value, name.span.start) {
+                                *operand =
+                                    Operand::String(Spanned::new(value.to_string(), name.span));
+                            }
+                        }
+                    }
+                }
+            }
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                self.condition(left);
+                self.condition(right);
+            }
+            Condition::Not(condition) => self.condition(condition),
+        }
+    }
+
+    fn process(&mut self, process: &mut Process) {
+        self.interpolate(&mut process.name);
+        self.args(&mut process.args);
+
+        if let Some(condition) = &mut process.condition {
+            self.condition(&mut condition.value);
+        }
+
+        for handler in process.handlers_mut() {
+            match handler {
+                Handler::Process(name, args) => {
+                    self.interpolate(name);
+                    self.args(args);
+                }
+                Handler::Concurrent(group, _) | Handler::Sequential(group) => {
+                    for task in &mut group.tasks {
+                        self.task(task);
+                    }
+                }
+            }
+        }
+
+        for monitor in &mut process.log_monitors {
+            self.interpolate(monitor);
+        }
+    }
+
+    /// Rebuilds `text` from its quoted form in the source, substituting
+    /// variables. Bare names cannot refer to variables and are left as is.
+    fn interpolate(&mut self, text: &mut Spanned<String>) {
+        let quoted = Input::new(self.source).advance(text.span.start);
+
+        let Ok(parsed) = quoted_segments(quoted) else {
+            return;
+        };
+
+        let mut value = String::new();
+
+        for segment in parsed.output {
+            match segment.value {
+                Segment::Text(text) => value.push_str(&text),
+                Segment::Variable(name) => match self.params.get(&name) {
+                    Some(Value::String(param)) => value.push_str(param),
+                    Some(Value::Number(param)) => value.push_str(param.as_str()),
+                    Some(Value::Boolean(param)) => value.push_str(&param.to_string()),
+                    // Reported where the argument was passed.
+                    Some(Value::Reference(_)) => {}
+                    None => match self.lookup(&name, segment.span.start) {
+                        Some(found) => value.push_str(found),
+                        None => self.undefined.push(segment.span),
+                    },
+                },
+            }
+        }
+
+        text.value = value;
+    }
+
+    fn lookup(&self, name: &str, offset: usize) -> Option<&str> {
+        self.declared(name, offset)
+            .or_else(|| self.vars.get(name).map(String::as_str))
+    }
+
+    /// Finds the value of the last `let` of `name` that takes effect before
+    /// `offset`. Conditions only take their variables from these, leaving
+    /// the rest to be looked up when they are evaluated.
+    fn declared(&self, name: &str, offset: usize) -> Option<&str> {
+        self.declared
+            .iter()
+            .rev()
+            .find(|(declared, _, from)| declared == name && *from <= offset)
+            .map(|(_, value, _)| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arpx_job::job::job;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
+    use crate::generic::span::spanned;
+
+    #[test]
+    fn test_variable() -> Result<(), String> {
+        assert_eq!(
+            variable().parse(r#"let region = "us-east-1"; build;"#)?,
+            (
+                "build;",
+                Variable {
+                    name: spanned("region", 4),
+                    value: Spanned::new("us-east-1".to_string(), Span::new(13, 24)),
+                    span: Span::new(0, 25),
+                }
+            )
+        );
+        assert_eq!(
+            variable().parse("let = \"a\";"),
+            Err(ParseFailure::new(
+                "= \"a\";",
+                vec![Expected::Label("variable name")]
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolate() -> Result<(), String> {
+        let source = r#"let v = "1"; a ? { "b${v}"; } : "c\${v}"; @"log-${v}" "${w}";"#;
+        let mut parsed = job().parse(source)?.output;
+
+        let undefined = interpolate(&mut parsed, source, &HashMap::new());
+        let process = parsed.tasks[0].processes()[0];
+
+        assert!(matches!(
+            &process.onsucceed,
+            Some(Handler::Sequential(group)) if group.tasks[0].processes()[0].name.value == "b1"
+        ));
+        assert!(matches!(
+            &process.onfail,
+            Some(Handler::Process(name, _)) if name.value == "c${v}"
+        ));
+        assert_eq!(process.log_monitors[0].value, "log-1");
+        assert_eq!(undefined, vec![Span::new(55, 59)]);
+        Ok(())
+    }
+}
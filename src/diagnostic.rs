@@ -0,0 +1,355 @@
+use std::collections::BTreeSet;
+
+use crate::error::ParseError;
+use crate::generic::combinators::parser::format_expected;
+use crate::generic::span::{Span, Spanned};
+use crate::line_index::LineIndex;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn style(self) -> &'static str {
+        match self {
+            Severity::Error => BOLD_RED,
+            Severity::Warning => BOLD_YELLOW,
+        }
+    }
+}
+
+/// A message about a span of source, ready to be rendered. `label` is shown
+/// under `span` itself, while `labels` point at related spans.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+    pub labels: Vec<Spanned<String>>,
+    pub notes: Vec<String>,
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let label = if error.expected.is_empty() {
+            None
+        } else {
+            Some(format!("expected {}", format_expected(&error.expected)))
+        };
+
+        Diagnostic {
+            severity: Severity::Error,
+            message: error.message(),
+            span: error.span,
+            label,
+            labels: error.labels.clone(),
+            notes: error.notes.clone(),
+        }
+    }
+}
+
+/// Renders diagnostics as source excerpts with a line number gutter and the
+/// offending spans underlined, in the style of compiler errors.
+#[derive(Clone, Debug, Default)]
+pub struct Renderer {
+    file_name: Option<String>,
+    color: bool,
+}
+
+struct Mark<'d> {
+    span: Span,
+    text: Option<&'d str>,
+    primary: bool,
+}
+
+impl Renderer {
+    #[must_use]
+    pub fn new() -> Self {
+        Renderer::default()
+    }
+
+    #[must_use]
+    pub fn file_name(mut self, file_name: impl Into<String>) -> Self {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Enables ANSI colors, which are off by default.
+    #[must_use]
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    #[must_use]
+    pub fn render_error(&self, error: &ParseError, source: &str) -> String {
+        self.render(&Diagnostic::from(error), source)
+    }
+
+    #[must_use]
+    pub fn render(&self, diagnostic: &Diagnostic, source: &str) -> String {
+        let index = LineIndex::new(source);
+        let marks: Vec<Mark> = std::iter::once(Mark {
+            span: diagnostic.span,
+            text: diagnostic.label.as_deref(),
+            primary: true,
+        })
+        .chain(diagnostic.labels.iter().map(|label| Mark {
+            span: label.span,
+            text: Some(label.value.as_str()),
+            primary: false,
+        }))
+        .collect();
+
+        let lines: BTreeSet<u32> = marks
+            .iter()
+            .flat_map(|mark| {
+                let (first, last) = line_range(&index, mark.span);
+                first..=last
+            })
+            .collect();
+        let width = lines.iter().last().map_or(1, |line| line.to_string().len());
+        let gutter = " ".repeat(width);
+        let bar = self.paint(BOLD_BLUE, "|");
+
+        let start = index.coordinates(diagnostic.span.start);
+        let location = match &self.file_name {
+            Some(file_name) => format!("{}:{}:{}", file_name, start.line, start.column + 1),
+            None => format!("{}:{}", start.line, start.column + 1),
+        };
+
+        let mut out = vec![
+            format!(
+                "{}{}",
+                self.paint(diagnostic.severity.style(), diagnostic.severity.name()),
+                self.paint(BOLD, &format!(": {}", diagnostic.message))
+            ),
+            format!("{}{} {}", gutter, self.paint(BOLD_BLUE, "-->"), location),
+            format!("{} {}", gutter, bar),
+        ];
+
+        let mut previous = None;
+
+        for line in lines {
+            if previous.is_some_and(|previous| line > previous + 1) {
+                out.push(self.paint(BOLD_BLUE, "..."));
+            }
+
+            previous = Some(line);
+
+            let text = index.line_text(line).unwrap_or_default();
+
+            out.push(
+                format!(
+                    "{} {} {}",
+                    self.paint(BOLD_BLUE, &format!("{:>width$}", line, width = width)),
+                    bar,
+                    text
+                )
+                .trim_end()
+                .to_string(),
+            );
+
+            for mark in &marks {
+                if let Some(underline) = self.underline(&index, mark, line, diagnostic) {
+                    out.push(format!("{} {} {}", gutter, bar, underline));
+                }
+            }
+        }
+
+        if !diagnostic.notes.is_empty() {
+            out.push(format!("{} {}", gutter, bar));
+        }
+
+        for note in &diagnostic.notes {
+            out.push(format!(
+                "{} {} {}",
+                gutter,
+                self.paint(BOLD_BLUE, "="),
+                self.paint(BOLD, &format!("note: {}", note))
+            ));
+        }
+
+        out.join("\n")
+    }
+
+    /// Returns the underline for the part of `mark` on `line`, with the
+    /// mark's text after it if this is the last line the mark covers.
+    fn underline(
+        &self,
+        index: &LineIndex,
+        mark: &Mark,
+        line: u32,
+        diagnostic: &Diagnostic,
+    ) -> Option<String> {
+        let (first, last) = line_range(index, mark.span);
+
+        if line < first || line > last {
+            return None;
+        }
+
+        let text = index.line_text(line)?;
+        let line_start = index.offset(line, 0)?;
+        let start = mark.span.start.max(line_start) - line_start;
+        let end = mark.span.end.min(line_start + text.len()).max(line_start) - line_start;
+        let start = start.min(text.len());
+        let end = end.max(start);
+
+        // Tabs are kept so that the underline lines up however wide the
+        // terminal draws them.
+        let padding: String = text
+            .get(..start)
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = text
+            .get(start..end)
+            .map_or(0, |marked| marked.chars().count());
+        let marker = if mark.primary { "^" } else { "-" };
+        let style = if mark.primary {
+            diagnostic.severity.style()
+        } else {
+            BOLD_BLUE
+        };
+
+        let mut underline = self.paint(style, &marker.repeat(width.max(1)));
+
+        if let (Some(text), true) = (mark.text, line == last) {
+            underline = format!("{} {}", underline, self.paint(style, text));
+        }
+
+        Some(format!("{}{}", padding, underline))
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+fn line_range(index: &LineIndex, span: Span) -> (u32, u32) {
+    let first = index.coordinates(span.start).line;
+    let last = index.coordinates(span.end.max(span.start + 1) - 1).line;
+
+    (first, last.max(first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_job;
+
+    #[test]
+    fn test_render_error() {
+        let source = "loop1 ? loop2;\n[loop3 ? loop4 : loop5 ~ loop6;]\n";
+        let error = parse_job(source).unwrap_err();
+
+        let expected = r#"error: this handler never runs because the process has no timeout
+ --> jobs/ci.arpx:2:26
+  |
+2 | [loop3 ? loop4 : loop5 ~ loop6;]
+  |                          ^^^^^
+  |
+  = note: add a modifier such as `timeout=10m` after the process name"#;
+
+        assert_eq!(
+            Renderer::new()
+                .file_name("jobs/ci.arpx")
+                .render_error(&error, source),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_render_error_location_matches_display() {
+        let source = "loop1;\n  loop2 ]";
+        let error = parse_job(source).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("unexpected `]` at line 2, column 9,"));
+        assert!(Renderer::new()
+            .render_error(&error, source)
+            .contains("--> 2:9\n"));
+    }
+
+    #[test]
+    fn test_render_error_at_end_of_input() {
+        let source = "loop1 ?";
+        let error = parse_job(source).unwrap_err();
+
+        let expected = r#"error: unexpected end of input
+ --> 1:8
+  |
+1 | loop1 ?
+  |        ^ expected exit code, `[`, `{` or process name"#;
+
+        assert_eq!(Renderer::new().render_error(&error, source), expected);
+    }
+
+    #[test]
+    fn test_render_multi_line_and_distant_labels() {
+        let source = "[\n\tloop1;\n\tloop2;\n]\nloop3;\nloop4;\nloop5 ]";
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            message: "something to look at".to_string(),
+            span: Span::new(source.len() - 1, source.len()),
+            label: Some("here".to_string()),
+            labels: vec![Spanned::new("this block".to_string(), Span::new(0, 19))],
+            notes: Vec::new(),
+        };
+
+        let expected = "warning: something to look at
+ --> 7:7
+  |
+1 | [
+  | -
+2 | \tloop1;
+  | -------
+3 | \tloop2;
+  | -------
+4 | ]
+  | - this block
+...
+7 | loop5 ]
+  |       ^ here";
+
+        assert_eq!(Renderer::new().render(&diagnostic, source), expected);
+    }
+
+    #[test]
+    fn test_render_with_color() {
+        let source = "loop1 ?";
+        let error = parse_job(source).unwrap_err();
+
+        let rendered = Renderer::new().color(true).render_error(&error, source);
+
+        assert!(
+            rendered.starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: unexpected end of input\x1b[0m")
+        );
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+        assert!(!Renderer::new()
+            .render_error(&error, source)
+            .contains('\x1b'));
+    }
+}
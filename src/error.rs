@@ -1,52 +1,187 @@
-use std::cmp::{max, min};
-
-type TextCoordinates = (u32, u32);
-
-pub type ParseErrorContext = (TextCoordinates, String);
-
-pub fn get_parse_error_context(source: &str, remaining: &str) -> ParseErrorContext {
-    let char_pos = source.len() - remaining.len();
-
-    let error_loc_slice = format!(
-        " !ERROR-> {error_char}",
-        error_char = source.as_bytes()[char_pos] as char
-    );
-    let before_pos = min(max(20, char_pos) - 20, char_pos);
-    let after_pos = min(source.len() - 1, char_pos + 20);
-    let context_before = &source[before_pos..char_pos];
-    let context_after = &source[(char_pos + 1)..after_pos];
-    let context = format!(
-        "{before}{error}{after}",
-        before = context_before,
-        error = error_loc_slice,
-        after = context_after
-    );
-
-    (get_text_coordinates(&source[..char_pos]), context)
+use std::error::Error;
+use std::fmt;
+
+use crate::arpx_job::is_name_continue;
+use crate::generic::builtin_matchers::whitespace::skip_trivia;
+use crate::generic::combinators::parser::{format_expected, ParseFailure, Rejection};
+use crate::generic::span::{Span, Spanned};
+use crate::line_index::{LineIndex, TextCoordinates};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedToken,
+    UnexpectedEndOfInput,
+    /// Input that is well formed but not allowed, such as an argument given
+    /// twice or an empty range of exit codes. `reason` says why.
+    Invalid,
+    /// A `${name}` reference to a variable that is neither declared with
+    /// `let` before it nor passed to `parse_job_with_vars`.
+    UndefinedVariable,
+    /// A task that calls a macro with the wrong arguments, or in a way that
+    /// cannot be expanded. `reason` says why.
+    InvalidMacroCall,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    pub start: TextCoordinates,
+    pub end: TextCoordinates,
+    pub expected: Vec<String>,
+    pub found: Option<String>,
+    /// Why the input is not allowed, for `Invalid` and `InvalidMacroCall`
+    /// errors.
+    pub reason: Option<String>,
+    pub labels: Vec<Spanned<String>>,
+    pub notes: Vec<String>,
+}
+
+impl ParseError {
+    /// Builds an error for the first token of `remaining`, skipping any
+    /// leading whitespace and comments. `remaining` must be a suffix of the
+    /// indexed source.
+    pub(crate) fn new(index: &LineIndex, remaining: &str) -> Self {
+        let remaining = skip_trivia(remaining);
+        let start = index.source().len() - remaining.len();
+        let found = next_token(remaining);
+        let end = start + found.map_or(0, str::len);
+
+        let kind = match found {
+            Some(_) => ParseErrorKind::UnexpectedToken,
+            None => ParseErrorKind::UnexpectedEndOfInput,
+        };
+
+        ParseError {
+            kind,
+            span: Span::new(start, end),
+            start: index.coordinates(start),
+            end: index.coordinates(end),
+            expected: Vec::new(),
+            found: found.map(str::to_string),
+            reason: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Builds an error for `failure`: at the construct it rejected, if any,
+    /// and otherwise at the first token it could not parse.
+    pub(crate) fn from_failure(index: &LineIndex, failure: &ParseFailure) -> Self {
+        match &failure.rejection {
+            Some(rejection) => ParseError {
+                labels: failure.labels.clone(),
+                notes: failure.notes.clone(),
+                ..ParseError::rejected(index, ParseErrorKind::Invalid, rejection)
+            },
+            None => ParseError {
+                expected: failure.expected.iter().map(ToString::to_string).collect(),
+                labels: failure.labels.clone(),
+                notes: failure.notes.clone(),
+                ..ParseError::new(index, failure.remaining)
+            },
+        }
+    }
+
+    /// Builds an error of `kind` for the span that `rejection` labels with
+    /// the reason it was turned down.
+    fn rejected(index: &LineIndex, kind: ParseErrorKind, rejection: &Spanned<String>) -> Self {
+        let span = rejection.span;
+
+        ParseError {
+            kind,
+            span,
+            start: index.coordinates(span.start),
+            end: index.coordinates(span.end),
+            expected: Vec::new(),
+            found: index.source().get(span.start..span.end).map(str::to_string),
+            reason: Some(rejection.value.clone()),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Builds an error for the `${name}` reference at `span`.
+    pub(crate) fn undefined_variable(index: &LineIndex, span: Span) -> Self {
+        ParseError {
+            kind: ParseErrorKind::UndefinedVariable,
+            span,
+            start: index.coordinates(span.start),
+            end: index.coordinates(span.end),
+            expected: Vec::new(),
+            found: Some(index.source()[span.start..span.end].to_string()),
+            reason: None,
+            labels: Vec::new(),
+            notes: vec![
+                "declare the variable with `let` before using it, or pass in its value".to_string(),
+            ],
+        }
+    }
+
+    /// Builds an error for a macro call that `rejection` explains.
+    pub(crate) fn invalid_macro_call(index: &LineIndex, rejection: &Rejection) -> Self {
+        ParseError {
+            notes: rejection.notes.clone(),
+            ..ParseError::rejected(index, ParseErrorKind::InvalidMacroCall, &rejection.label)
+        }
+    }
+
+    /// Describes the error without its position or expectations.
+    #[must_use]
+    pub fn message(&self) -> String {
+        match (self.kind, &self.found) {
+            (ParseErrorKind::Invalid | ParseErrorKind::InvalidMacroCall, _) => {
+                self.reason.clone().unwrap_or_default()
+            }
+            (ParseErrorKind::UnexpectedToken, Some(found)) => format!("unexpected `{}`", found),
+            (ParseErrorKind::UndefinedVariable, Some(found)) => {
+                format!("`{}` is not defined", found)
+            }
+            _ => "unexpected end of input".to_string(),
+        }
+    }
 }
 
-fn get_text_coordinates(text: &str) -> TextCoordinates {
-    let mut line = 1;
-    let mut col = 0;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message(),
+            self.start.line,
+            self.start.column + 1
+        )?;
 
-    for c in text.chars() {
-        if c == '\n' {
-            col = 0;
-            line += 1;
-        } else {
-            col += 1;
+        if !self.expected.is_empty() {
+            write!(f, ", expected {}", format_expected(&self.expected))?;
         }
+
+        Ok(())
     }
+}
+
+impl Error for ParseError {}
 
-    (line, col)
+/// Returns the token at the start of `text`: a run of process name
+/// characters, or otherwise a single character.
+fn next_token(text: &str) -> Option<&str> {
+    let first = text.chars().next()?;
+    let len = if is_name_continue(first) {
+        text.find(|c: char| !is_name_continue(c))
+            .unwrap_or(text.len())
+    } else {
+        first.len_utf8()
+    };
+
+    Some(&text[..len])
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{get_parse_error_context, ParseErrorContext};
+    use super::*;
 
     #[test]
-    fn test_parse_error_context() -> Result<(), ParseErrorContext> {
+    fn test_parse_error() {
         let source = r#"Lorem ipsum dolor sit amet, consectetur adipiscing elit.
 Phasellus vulputate euismod luctus. Praesent viverra
 dapibus nisl at feugiat. Fusce pellentesque augue ac nibh
@@ -65,14 +200,77 @@ eros eu dolor faucibus interdum. Phasellus blandit sed
 ipsum sed vestibulum."#;
         let remaining = &source[651..];
 
-        let result = get_parse_error_context(source, remaining);
+        let expected = ParseError {
+            kind: ParseErrorKind::UnexpectedToken,
+            span: Span::new(651, 654),
+            start: TextCoordinates {
+                line: 12,
+                column: 25,
+                utf8_column: 25,
+                utf16_column: 25,
+            },
+            end: TextCoordinates {
+                line: 12,
+                column: 28,
+                utf8_column: 28,
+                utf16_column: 28,
+            },
+            expected: Vec::new(),
+            found: Some("Sed".to_string()),
+            reason: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        };
 
-        let expected = (
-            (12, 25),
-            "ssim quis ac lacus.  !ERROR-> Sed suscipit consequ".to_string(),
+        assert_eq!(
+            ParseError::new(&LineIndex::new(source), remaining),
+            expected
         );
+    }
 
-        assert_eq!(result, expected);
-        Ok(())
+    #[test]
+    fn test_parse_error_at_end_of_input() {
+        let source = "loop1 ?\n";
+        let remaining = &source[source.len()..];
+
+        let error = ParseError::new(&LineIndex::new(source), remaining);
+
+        assert_eq!(error.kind, ParseErrorKind::UnexpectedEndOfInput);
+        assert_eq!(error.span, Span::new(8, 8));
+        assert_eq!((error.start.line, error.start.column), (2, 0));
+        assert_eq!(error.found, None);
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        let source = "loop1 ? loop2 ]";
+        let mut error = ParseError::new(&LineIndex::new(source), &source[14..]);
+
+        assert_eq!(error.to_string(), "unexpected `]` at line 1, column 15");
+
+        error.expected = vec!["`;`".to_string(), "`:`".to_string(), "`@`".to_string()];
+
+        assert_eq!(
+            error.to_string(),
+            "unexpected `]` at line 1, column 15, expected `;`, `:` or `@`"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_with_multi_byte_characters() {
+        let source = "日本 ? 💥 ]";
+        let error = ParseError::new(&LineIndex::new(source), &source[13..]);
+
+        assert_eq!(error.found, Some("]".to_string()));
+        assert_eq!(error.span, Span::new(14, 15));
+        assert_eq!(
+            error.start,
+            TextCoordinates {
+                line: 1,
+                column: 7,
+                utf8_column: 14,
+                utf16_column: 8,
+            }
+        );
     }
 }
@@ -1,22 +1,34 @@
-use crate::generic::combinators::parser::ParseResult;
+use crate::generic::combinators::parser::{Expected, ParseFailure, ParseResult, Parsed};
+use crate::generic::input::Input;
 
-pub fn any_char(input: &str) -> ParseResult<char> {
-    match input.chars().next() {
-        Some(next) => Ok((&input[next.len_utf8()..], next)),
-        _ => Err(input),
+pub fn any_char(input: Input<'_>) -> ParseResult<'_, char> {
+    match input.as_str().chars().next() {
+        Some(next) => Ok(Parsed::new(input.advance(next.len_utf8()), next)),
+        _ => Err(ParseFailure::new(
+            input.as_str(),
+            vec![Expected::Label("any character")],
+        )),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generic::combinators::parser::Parser;
 
     #[test]
     fn test_any_char() -> Result<(), String> {
-        let parse = any_char;
+        let parser = any_char;
 
-        assert_eq!(parse("foo")?, ("oo", 'f'));
-        assert_eq!(parse(""), Err(""));
+        assert_eq!(parser.parse("foo")?, ("oo", 'f'));
+        assert_eq!(parser.parse("💥!")?, ("!", '💥'));
+        assert_eq!(
+            parser.parse(""),
+            Err(ParseFailure::new(
+                "",
+                vec![Expected::Label("any character")]
+            ))
+        );
         Ok(())
     }
 }
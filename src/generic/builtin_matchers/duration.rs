@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use crate::generic::combinators::parser::{Expected, ParseFailure, ParseResult, Parsed};
+use crate::generic::input::Input;
+
+/// Units from smallest to largest, which is also the order they are tried
+/// in, so that `ms` wins over `m`.
+const UNITS: [(&str, u64); 4] = [("ms", 1), ("s", 1_000), ("m", 60_000), ("h", 3_600_000)];
+
+/// Parses a duration such as `500ms`, `2s`, `10m` or `1h`, or a compound
+/// duration such as `1h30m` or `1m30s500ms` whose units run from largest to
+/// smallest, each appearing at most once.
+pub fn duration(input: Input<'_>) -> ParseResult<'_, Duration> {
+    let (mut input, (mut unit, mut total)) = component(input, UNITS.len())?;
+
+    while input
+        .as_str()
+        .starts_with(|next: char| next.is_ascii_digit())
+    {
+        let (remaining, (next_unit, millis)) = component(input, unit).map_err(|failure| {
+            failure.with_note(
+                "compound durations list units from largest to smallest, each at most once",
+            )
+        })?;
+
+        total = total
+            .checked_add(millis)
+            .ok_or_else(|| too_long(input.as_str()))?;
+        input = remaining;
+        unit = next_unit;
+    }
+
+    Ok(Parsed::new(input, Duration::from_millis(total)))
+}
+
+/// Parses an amount and one of the units before `smaller_than` in `UNITS`,
+/// returning the index of the unit and the amount in milliseconds.
+fn component(
+    input: Input<'_>,
+    smaller_than: usize,
+) -> Result<(Input<'_>, (usize, u64)), ParseFailure<'_>> {
+    let text = input.as_str();
+    let len = text
+        .find(|next: char| !next.is_ascii_digit())
+        .unwrap_or(text.len());
+
+    if len == 0 {
+        return Err(ParseFailure::new(text, vec![Expected::Label("duration")]));
+    }
+
+    let rest = &text[len..];
+    let units = &UNITS[..smaller_than];
+    let Some(index) = units.iter().position(|&(unit, _)| rest.starts_with(unit)) else {
+        return Err(ParseFailure::new(
+            rest,
+            units
+                .iter()
+                .map(|&(unit, _)| Expected::Literal(unit))
+                .collect(),
+        ));
+    };
+    let (unit, millis) = units[index];
+
+    match text[..len]
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(millis))
+    {
+        Some(millis) => Ok((input.advance(len + unit.len()), (index, millis))),
+        None => Err(too_long(text)),
+    }
+}
+
+fn too_long(remaining: &str) -> ParseFailure<'_> {
+    ParseFailure::new(remaining, vec![Expected::Label("duration")])
+        .with_note("durations must be shorter than about 584 million years")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::Parser;
+
+    #[test]
+    fn test_duration() -> Result<(), String> {
+        assert_eq!(duration.parse("500ms)")?, (")", Duration::from_millis(500)));
+        assert_eq!(duration.parse("2s,")?, (",", Duration::from_secs(2)));
+        assert_eq!(duration.parse("10m")?, ("", Duration::from_secs(600)));
+        assert_eq!(duration.parse("1h")?, ("", Duration::from_secs(3600)));
+        assert_eq!(duration.parse("0s")?, ("", Duration::ZERO));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_duration() {
+        assert_eq!(
+            duration.parse("s"),
+            Err(ParseFailure::new("s", vec![Expected::Label("duration")]))
+        );
+        assert_eq!(
+            duration.parse("2d"),
+            Err(ParseFailure::new(
+                "d",
+                vec![
+                    Expected::Literal("ms"),
+                    Expected::Literal("s"),
+                    Expected::Literal("m"),
+                    Expected::Literal("h")
+                ]
+            ))
+        );
+        assert_eq!(
+            duration.parse("99999999999999999999s"),
+            Err(
+                ParseFailure::new("99999999999999999999s", vec![Expected::Label("duration")])
+                    .with_note("durations must be shorter than about 584 million years")
+            )
+        );
+    }
+
+    #[test]
+    fn test_compound_duration() -> Result<(), String> {
+        assert_eq!(duration.parse("1h30m;")?, (";", Duration::from_secs(5400)));
+        assert_eq!(
+            duration.parse("1m30s500ms")?,
+            ("", Duration::from_millis(90_500))
+        );
+        assert_eq!(duration.parse("2h5s")?, ("", Duration::from_secs(7205)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compound_duration_units_must_decrease() {
+        let note = "compound durations list units from largest to smallest, each at most once";
+
+        assert_eq!(
+            duration.parse("30m1h"),
+            Err(
+                ParseFailure::new("h", vec![Expected::Literal("ms"), Expected::Literal("s")])
+                    .with_note(note)
+            )
+        );
+        assert_eq!(
+            duration.parse("1s2s"),
+            Err(ParseFailure::new("s", vec![Expected::Literal("ms")]).with_note(note))
+        );
+        assert_eq!(
+            duration.parse("1ms2"),
+            Err(ParseFailure::new("", Vec::new()).with_note(note))
+        );
+    }
+}
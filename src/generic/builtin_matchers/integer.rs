@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use crate::generic::combinators::parser::{
+    BoxedParser, Expected, ParseFailure, Parsed, Parser, Rejection,
+};
+use crate::generic::input::Input;
+use crate::generic::span::Spanned;
+
+/// Returns a parser for a run of decimal digits that fits in `T`, such as an
+/// exit code or a count. Anything else, including a number too large for
+/// `T`, fails expecting `label`.
+pub fn unsigned<'a, T: FromStr>(label: &'static str) -> impl Parser<'a, T> {
+    move |input: Input<'a>| {
+        let text = input.as_str();
+        let len = text
+            .find(|next: char| !next.is_ascii_digit())
+            .unwrap_or(text.len());
+
+        match text[..len].parse() {
+            Ok(number) => Ok(Parsed::new(input.advance(len), number)),
+            Err(_) => Err(ParseFailure::new(text, vec![Expected::Label(label)])),
+        }
+    }
+}
+
+/// Returns a parser for a count of at least one, which rejects a count of
+/// zero with `reason` and `note`.
+pub fn positive_count<'a>(
+    label: &'static str,
+    reason: &'static str,
+    note: &'static str,
+) -> BoxedParser<'a, Spanned<u32>> {
+    unsigned(label).spanned().validate(move |count| {
+        if count.value == 0 {
+            Err(Rejection::new(count.span, reason).with_note(note))
+        } else {
+            Ok(())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::span::Span;
+
+    #[test]
+    fn test_unsigned() -> Result<(), String> {
+        assert_eq!(unsigned::<u32>("count").parse("42;")?, (";", 42));
+        assert_eq!(unsigned::<i32>("exit code").parse("0")?, ("", 0));
+        assert_eq!(
+            unsigned::<u32>("count").parse("-1"),
+            Err(ParseFailure::new("-1", vec![Expected::Label("count")]))
+        );
+        assert_eq!(
+            unsigned::<u32>("count").parse("99999999999"),
+            Err(ParseFailure::new(
+                "99999999999",
+                vec![Expected::Label("count")]
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_positive_count() -> Result<(), String> {
+        let parser = positive_count("count", "too few", "use more");
+
+        assert_eq!(parser.parse("3 ")?, (" ", Spanned::new(3, Span::new(0, 1))));
+        assert_eq!(
+            parser.parse("00 "),
+            Err(ParseFailure::rejected(
+                " ",
+                Rejection::new(Span::new(0, 2), "too few").with_note("use more")
+            ))
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,41 @@
-use crate::generic::combinators::parser::Parser;
+use crate::generic::builtin_matchers::whitespace::{comment, space0, whitespace_char};
+use crate::generic::combinators::either::either;
+use crate::generic::combinators::left::left;
+use crate::generic::combinators::pair::pair;
+use crate::generic::combinators::parser::{Expected, ParseFailure, Parsed, Parser};
+use crate::generic::input::Input;
 
 pub fn literal<'a>(expected: &'static str) -> impl Parser<'a, ()> {
-    move |input: &'a str| match input.get(0..expected.len()) {
-        Some(next) if next == expected => Ok((&input[expected.len()..], ())),
-        _ => Err(input),
+    move |input: Input<'a>| match input.as_str().get(0..expected.len()) {
+        Some(next) if next == expected => Ok(Parsed::new(input.advance(expected.len()), ())),
+        _ => Err(ParseFailure::new(
+            input.as_str(),
+            vec![Expected::Literal(expected)],
+        )),
+    }
+}
+
+/// Parses `word` followed by whitespace or a comment, and any more of either,
+/// or directly by the `{` of a block, so that names that merely start with
+/// it are left alone. The `{` is left for the block to parse.
+pub fn keyword<'a>(word: &'static str) -> impl Parser<'a, ()> {
+    let boundary = either(whitespace_char().map(|_| ()), comment.map(|_| ()));
+
+    left(
+        literal(word),
+        either(pair(boundary, space0()).map(|_| ()), followed_by("{")),
+    )
+}
+
+/// Succeeds without consuming any input if the input starts with
+/// `expected`, and fails without expectations otherwise.
+pub fn followed_by<'a>(expected: &'static str) -> impl Parser<'a, ()> {
+    move |input: Input<'a>| {
+        if input.as_str().starts_with(expected) {
+            Ok(Parsed::new(input, ()))
+        } else {
+            Err(ParseFailure::new(input.as_str(), Vec::new()))
+        }
     }
 }
 
@@ -17,7 +49,24 @@ mod tests {
 
         assert_eq!(parser.parse("foo")?, ("", ()));
         assert_eq!(parser.parse("foobar")?, ("bar", ()));
-        assert_eq!(parser.parse("bar"), Err("bar"));
+        assert_eq!(
+            parser.parse("bar"),
+            Err(ParseFailure::new("bar", vec![Expected::Literal("foo")]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyword() -> Result<(), String> {
+        let parser = keyword("when");
+
+        assert_eq!(parser.parse("when \n x")?, ("x", ()));
+        assert_eq!(parser.parse("when/* a */# b\n x")?, ("x", ()));
+        assert_eq!(parser.parse("when{ x")?, ("{ x", ()));
+        assert_eq!(
+            parser.parse("whenever"),
+            Err(ParseFailure::new("ever", Vec::new()))
+        );
         Ok(())
     }
 }
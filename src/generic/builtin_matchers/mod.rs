@@ -1,3 +1,6 @@
 pub mod any_char;
+pub mod duration;
+pub mod integer;
 pub mod literal;
+pub mod string;
 pub mod whitespace;
@@ -0,0 +1,303 @@
+use std::fmt::Write;
+
+use crate::arpx_job::{is_name_continue, is_name_start};
+use crate::generic::combinators::parser::{Expected, ParseFailure, ParseResult, Parsed};
+use crate::generic::input::Input;
+use crate::generic::span::Spanned;
+
+/// A piece of a quoted string: text, or a `${name}` reference to a
+/// variable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+/// Parses a double-quoted string on a single line, unescaping `\"`, `\\`,
+/// `\n`, `\$` and `\u{...}` (one to six hex digits naming a Unicode scalar
+/// value). Variable references are kept as written, so use
+/// `quoted_segments` to substitute them.
+pub fn quoted_string(input: Input<'_>) -> ParseResult<'_, String> {
+    quoted_segments(input).map(|parsed| {
+        let value = parsed
+            .output
+            .iter()
+            .map(|segment| match &segment.value {
+                Segment::Text(text) => text.clone(),
+                Segment::Variable(name) => format!("${{{}}}", name),
+            })
+            .collect();
+
+        Parsed::new(parsed.remaining, value)
+    })
+}
+
+/// Parses a double-quoted string like `quoted_string`, splitting it into
+/// text and `${name}` references. A `$` that does not start a reference
+/// stands for itself, as does `\$`.
+pub fn quoted_segments(input: Input<'_>) -> ParseResult<'_, Vec<Spanned<Segment>>> {
+    let text = input.as_str();
+
+    let Some(body) = text.strip_prefix('"') else {
+        return Err(ParseFailure::new(text, vec![Expected::Literal("\"")]));
+    };
+
+    let mut segments = Vec::new();
+    let mut value = String::new();
+    let mut start = 1;
+    let mut offset = 0;
+
+    while let Some(next) = body[offset..].chars().next() {
+        match next {
+            '"' => {
+                push_text(&mut segments, &mut value, input, start, offset + 1);
+
+                return Ok(Parsed::new(input.advance(offset + 2), segments));
+            }
+            '\\' => match unescape(&body[offset..]) {
+                Some((unescaped, len)) => {
+                    value.push(unescaped);
+                    offset += len;
+                }
+                None => {
+                    return Err(ParseFailure::new(
+                        &body[offset..],
+                        vec![Expected::Label("escape sequence")],
+                    )
+                    .with_note("valid escapes are `\\\"`, `\\\\`, `\\n`, `\\$` and `\\u{...}`"))
+                }
+            },
+            '$' if body[offset..].starts_with("${") => {
+                let reference = &body[offset + 2..];
+                let len = reference
+                    .find(|next: char| !is_name_continue(next))
+                    .unwrap_or(reference.len());
+
+                if !reference.starts_with(is_name_start) {
+                    return Err(ParseFailure::new(
+                        reference,
+                        vec![Expected::Label("variable name")],
+                    ));
+                }
+
+                if !reference[len..].starts_with('}') {
+                    return Err(ParseFailure::new(
+                        &reference[len..],
+                        vec![Expected::Literal("}")],
+                    ));
+                }
+
+                push_text(&mut segments, &mut value, input, start, offset + 1);
+
+                let end = offset + len + 4;
+                segments.push(Spanned::new(
+                    Segment::Variable(reference[..len].to_string()),
+                    input.advance(offset + 1).span_to(input.advance(end)),
+                ));
+                start = end;
+                offset = end - 1;
+            }
+            '\n' => break,
+            _ => {
+                value.push(next);
+                offset += next.len_utf8();
+            }
+        }
+    }
+
+    Err(ParseFailure::new(
+        &body[offset..],
+        vec![Expected::Literal("\"")],
+    ))
+}
+
+/// Ends the text segment that started at `start` and ends at `end`, both
+/// relative to `input`, unless it is empty.
+fn push_text(
+    segments: &mut Vec<Spanned<Segment>>,
+    value: &mut String,
+    input: Input<'_>,
+    start: usize,
+    end: usize,
+) {
+    if start < end {
+        segments.push(Spanned::new(
+            Segment::Text(std::mem::take(value)),
+            input.advance(start).span_to(input.advance(end)),
+        ));
+    }
+}
+
+/// Quotes `value` so that `quoted_string` parses it back unchanged.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut chars = value.chars().peekable();
+
+    while let Some(next) = chars.next() {
+        match next {
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            _ if next.is_control() => {
+                let _ = write!(quoted, "\\u{{{:x}}}", next as u32);
+            }
+            _ => quoted.push(next),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Decodes the escape sequence at the start of `text`, returning the
+/// character it stands for and the length of the sequence in bytes.
+fn unescape(text: &str) -> Option<(char, usize)> {
+    let rest = text.strip_prefix('\\')?;
+
+    match rest.chars().next()? {
+        '"' => Some(('"', 2)),
+        '\\' => Some(('\\', 2)),
+        'n' => Some(('\n', 2)),
+        '$' => Some(('$', 2)),
+        'u' => {
+            let digits = rest.strip_prefix("u{")?;
+            let len = digits.find('}')?;
+            let hex = &digits[..len];
+
+            if hex.is_empty() || hex.len() > 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            let unescaped = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+
+            Some((unescaped, 4 + len))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::combinators::parser::Parser;
+    use crate::generic::span::Span;
+
+    #[test]
+    fn test_quoted_string() -> Result<(), String> {
+        let parser = quoted_string;
+
+        assert_eq!(
+            parser.parse(r#""build:web";"#)?,
+            (";", "build:web".to_string())
+        );
+        assert_eq!(
+            parser.parse(r#""db migrate""#)?,
+            ("", "db migrate".to_string())
+        );
+        assert_eq!(
+            parser.parse(r#""say \"hi\"\\\n\u{1F980}\u{e9}" ?"#)?,
+            (" ?", "say \"hi\"\\\n🦀é".to_string())
+        );
+        assert_eq!(parser.parse(r#""""#)?, ("", String::new()));
+        assert_eq!(
+            parser.parse("build"),
+            Err(ParseFailure::new("build", vec![Expected::Literal("\"")]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unterminated_quoted_string() {
+        assert_eq!(
+            quoted_string.parse(r#""build"#),
+            Err(ParseFailure::new("", vec![Expected::Literal("\"")]))
+        );
+        assert_eq!(
+            quoted_string.parse("\"build\nweb\""),
+            Err(ParseFailure::new("\nweb\"", vec![Expected::Literal("\"")]))
+        );
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        for (example, remaining) in [
+            (r#""a\tb""#, r#"\tb""#),
+            (r#""\u{}""#, r#"\u{}""#),
+            (r#""\u{1234567}""#, r#"\u{1234567}""#),
+            (r#""\u{d800}""#, r#"\u{d800}""#),
+            (r#""\u{zz}""#, r#"\u{zz}""#),
+            (r#""\u{41""#, r#"\u{41""#),
+            ("\"\\", "\\"),
+        ] {
+            assert_eq!(
+                quoted_string.parse(example),
+                Err(
+                    ParseFailure::new(remaining, vec![Expected::Label("escape sequence")])
+                        .with_note("valid escapes are `\\\"`, `\\\\`, `\\n`, `\\$` and `\\u{...}`")
+                ),
+                "{}",
+                example
+            );
+        }
+    }
+
+    #[test]
+    fn test_quote_round_trips() -> Result<(), String> {
+        for value in [
+            "build:web",
+            "db migrate",
+            "say \"hi\"",
+            "a\\b",
+            "a\nb\tc\r",
+            "",
+            "🦀",
+            "${region} costs $5",
+        ] {
+            assert_eq!(quoted_string.parse(&quote(value))?, ("", value.to_string()));
+        }
+
+        assert_eq!(quote("a\"b\tc"), r#""a\"b\u{9}c""#);
+        assert_eq!(quote("${a} $b"), r#""\${a} $b""#);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quoted_segments() -> Result<(), String> {
+        let parsed = quoted_segments.parse(r#""deploy-${region}:${env}\${x} $1" ?"#)?;
+
+        assert_eq!(
+            parsed,
+            (
+                " ?",
+                vec![
+                    Spanned::new(Segment::Text("deploy-".to_string()), Span::new(1, 8)),
+                    Spanned::new(Segment::Variable("region".to_string()), Span::new(8, 17)),
+                    Spanned::new(Segment::Text(":".to_string()), Span::new(17, 18)),
+                    Spanned::new(Segment::Variable("env".to_string()), Span::new(18, 24)),
+                    Spanned::new(Segment::Text("${x} $1".to_string()), Span::new(24, 32)),
+                ]
+            )
+        );
+        assert_eq!(
+            quoted_string.parse(r#""a-${b}""#)?,
+            ("", "a-${b}".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_variable_references() {
+        assert_eq!(
+            quoted_segments.parse(r#""${}""#),
+            Err(ParseFailure::new(
+                r#"}""#,
+                vec![Expected::Label("variable name")]
+            ))
+        );
+        assert_eq!(
+            quoted_segments.parse(r#""${region""#),
+            Err(ParseFailure::new(r#"""#, vec![Expected::Literal("}")]))
+        );
+    }
+}
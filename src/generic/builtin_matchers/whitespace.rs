@@ -1,29 +1,144 @@
 use crate::generic::builtin_matchers::any_char::any_char;
+use crate::generic::builtin_matchers::string::quoted_string;
+use crate::generic::combinators::either::either;
 use crate::generic::combinators::left::left;
 use crate::generic::combinators::n::n;
-use crate::generic::combinators::parser::Parser;
+use crate::generic::combinators::parser::{Expected, ParseFailure, ParseResult, Parsed, Parser};
 use crate::generic::combinators::right::right;
+use crate::generic::input::Input;
+use crate::generic::span::Spanned;
 
+/// A comment, without its delimiters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Comment {
+    /// `# ...` up to the end of the line.
+    Line(String),
+    /// `/* ... */`, which may span lines.
+    Block(String),
+}
+
+/// Skips whitespace and comments on both sides of `parser`.
 pub fn whitespace_wrap<'a, P, A>(parser: P) -> impl Parser<'a, A>
 where
     P: 'a + Parser<'a, A>,
     A: 'a,
 {
-    right(space0(), left(parser, space0()))
+    past_trivia(right(space0(), left(parser, space0())))
+}
+
+/// Reports the failures of `parser`, including those it recovered from, past
+/// any whitespace and comments that follow them, so that they count as
+/// expected at the next token. Use it where a parser may stop just before
+/// trivia that the parser after it skips.
+pub fn past_trivia<'a, P, A>(parser: P) -> impl Parser<'a, A>
+where
+    P: Parser<'a, A>,
+{
+    let skip = |failure: ParseFailure<'a>| ParseFailure {
+        remaining: skip_trivia(failure.remaining),
+        ..failure
+    };
+
+    move |input| match parser.parse_input(input) {
+        Ok(parsed) => Ok(Parsed {
+            furthest: parsed.furthest.map(skip),
+            ..parsed
+        }),
+        Err(failure) => Err(skip(failure)),
+    }
 }
 
-pub fn space0<'a>() -> impl Parser<'a, Vec<char>> {
-    n(whitespace_char(), 0..)
+/// Skips any run of whitespace and comments, returning the comments.
+pub fn space0<'a>() -> impl Parser<'a, Vec<Spanned<Comment>>> {
+    n(
+        either(whitespace_char().map(|_| None), comment.spanned().map(Some)),
+        0..,
+    )
+    .map(|trivia| trivia.into_iter().flatten().collect())
 }
 
 pub fn whitespace_char<'a>() -> impl Parser<'a, char> {
     any_char.pred(|c| c.is_whitespace())
 }
 
+/// Parses a `#` or `/* */` comment. Input that does not start a comment
+/// fails without expectations, so that trivia never shows up in the list of
+/// what an error expected; only an unterminated block comment asks for more.
+pub fn comment(input: Input<'_>) -> ParseResult<'_, Comment> {
+    let text = input.as_str();
+
+    if let Some(rest) = text.strip_prefix('#') {
+        let len = rest.find('\n').unwrap_or(rest.len());
+        let line = &rest[..len];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        Ok(Parsed::new(
+            input.advance(1 + line.len()),
+            Comment::Line(line.to_string()),
+        ))
+    } else if let Some(rest) = text.strip_prefix("/*") {
+        match rest.find("*/") {
+            Some(len) => Ok(Parsed::new(
+                input.advance(len + 4),
+                Comment::Block(rest[..len].to_string()),
+            )),
+            None => Err(ParseFailure::new(
+                &text[text.len()..],
+                vec![Expected::Literal("*/")],
+            )),
+        }
+    } else {
+        Err(ParseFailure::new(text, Vec::new()))
+    }
+}
+
+/// Returns `text` from its first character that is neither whitespace nor
+/// part of a comment. `past_trivia` relies on this, so unlike `space0` it
+/// must not be built from combinators that merge failures.
+pub fn skip_trivia(text: &str) -> &str {
+    let mut input = Input::new(text);
+
+    loop {
+        let trimmed = input.as_str().trim_start();
+        input = input.advance(input.as_str().len() - trimmed.len());
+
+        match comment(input) {
+            Ok(parsed) => input = parsed.remaining,
+            Err(_) => return input.as_str(),
+        }
+    }
+}
+
+/// Collects every comment in `source`, passing over quoted strings. Only
+/// meaningful for sources that parse, where anything else that looks like a
+/// comment is one.
+pub fn comments(source: &str) -> Vec<Spanned<Comment>> {
+    let trivia = space0();
+    let mut input = Input::new(source);
+    let mut comments = Vec::new();
+
+    while let Some(next) = input.as_str().chars().next() {
+        match trivia.parse_input(input) {
+            Ok(parsed) if parsed.remaining != input => {
+                comments.extend(parsed.output);
+                input = parsed.remaining;
+            }
+            _ => match quoted_string(input) {
+                Ok(parsed) => input = parsed.remaining,
+                Err(_) => input = input.advance(next.len_utf8()),
+            },
+        }
+    }
+
+    comments
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::generic::builtin_matchers::literal::literal;
+    use crate::generic::combinators::optional::optional;
+    use crate::generic::span::Span;
 
     #[test]
     fn test_whitespace_wrap() -> Result<(), String> {
@@ -40,7 +155,10 @@ mod tests {
             ("", ())
         );
         assert_eq!(parser.parse("    foo        bar")?, ("bar", ()));
-        assert_eq!(parser.parse("   bar   "), Err("bar   "));
+        assert_eq!(
+            parser.parse("   bar   "),
+            Err(ParseFailure::new("bar   ", vec![Expected::Literal("foo")]))
+        );
         Ok(())
     }
 
@@ -49,8 +167,79 @@ mod tests {
         let parser = whitespace_char();
 
         assert_eq!(parser.parse(" ")?, ("", ' '));
-        assert_eq!(parser.parse("f"), Err("f"));
-        assert_eq!(parser.parse(""), Err(""));
+        assert_eq!(parser.parse("f"), Err(ParseFailure::new("f", Vec::new())));
+        assert_eq!(parser.parse(""), Err(ParseFailure::new("", Vec::new())));
         Ok(())
     }
+
+    #[test]
+    fn test_whitespace_wrap_skips_comments() -> Result<(), String> {
+        let parser = whitespace_wrap(literal("foo"));
+
+        assert_eq!(parser.parse("# before\n  foo # after")?, ("", ()));
+        assert_eq!(parser.parse("/* a */foo/* b\n c */bar")?, ("bar", ()));
+        assert_eq!(parser.parse("#foo\nfoo")?, ("", ()));
+        assert_eq!(
+            parser.parse("# foo"),
+            Err(ParseFailure::new("", vec![Expected::Literal("foo")]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_past_trivia() -> Result<(), String> {
+        let parser = past_trivia(left(literal("foo"), optional(literal("!"))));
+
+        let parsed = parser.parse_input(Input::new("foo # why\n bar"))?;
+
+        assert_eq!(parsed.remaining.as_str(), " # why\n bar");
+        assert_eq!(
+            parsed.furthest,
+            Some(ParseFailure::new("bar", vec![Expected::Literal("!")]))
+        );
+        assert_eq!(
+            parser.parse("  /* a */ bar"),
+            Err(ParseFailure::new("bar", vec![Expected::Literal("foo")]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_space0() -> Result<(), String> {
+        let parser = space0();
+
+        assert_eq!(
+            parser.parse(" # one\r\n/* two */ three")?,
+            (
+                "three",
+                vec![
+                    Spanned::new(Comment::Line(" one".to_string()), Span::new(1, 6)),
+                    Spanned::new(Comment::Block(" two ".to_string()), Span::new(8, 17)),
+                ]
+            )
+        );
+        assert_eq!(parser.parse("foo")?, ("foo", Vec::new()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        assert_eq!(
+            comment.parse("/* foo"),
+            Err(ParseFailure::new("", vec![Expected::Literal("*/")]))
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let comments = comments("loop1; # first\n/* second */ \"# not /* a comment\";");
+
+        assert_eq!(
+            comments,
+            vec![
+                Spanned::new(Comment::Line(" first".to_string()), Span::new(7, 14)),
+                Spanned::new(Comment::Block(" second ".to_string()), Span::new(15, 27)),
+            ]
+        );
+    }
 }
@@ -5,9 +5,12 @@ where
     P1: Parser<'a, A>,
     P2: Parser<'a, A>,
 {
-    move |input| match parser1.parse(input) {
+    move |input| match parser1.parse_input(input) {
         ok @ Ok(_) => ok,
-        Err(_) => parser2.parse(input),
+        Err(failure1) => match parser2.parse_input(input) {
+            Ok(parsed) => Ok(parsed.recovered_from(Some(failure1))),
+            Err(failure2) => Err(failure1.merge(failure2)),
+        },
     }
 }
 
@@ -15,6 +18,7 @@ where
 mod tests {
     use super::*;
     use crate::generic::builtin_matchers::literal::literal;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
 
     #[test]
     fn test_either() -> Result<(), String> {
@@ -22,7 +26,13 @@ mod tests {
 
         assert_eq!(parser.parse("foo")?, ("", ()));
         assert_eq!(parser.parse("bar")?, ("", ()));
-        assert_eq!(parser.parse("baz"), Err("baz"));
+        assert_eq!(
+            parser.parse("baz"),
+            Err(ParseFailure::new(
+                "baz",
+                vec![Expected::Literal("foo"), Expected::Literal("bar")]
+            ))
+        );
         Ok(())
     }
 }
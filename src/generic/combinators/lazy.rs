@@ -0,0 +1,64 @@
+use std::cell::OnceCell;
+
+use crate::generic::combinators::parser::Parser;
+
+/// Defers building a parser until input arrives, so that a grammar can refer
+/// to itself. A recursive rule wraps its own constructor, as in
+/// `either(right(literal("("), lazy(expression)), number)`; the recursion
+/// ends once the input no longer matches the nested form.
+///
+/// The parser is built on first use and kept, so later inputs reuse it. The
+/// `lazy` inside it has a cell of its own, so each level of nesting is built
+/// once, the first time input reaches that depth.
+pub fn lazy<'a, F, P, A>(build: F) -> impl Parser<'a, A>
+where
+    F: Fn() -> P,
+    P: Parser<'a, A>,
+{
+    let parser = OnceCell::new();
+
+    move |input| parser.get_or_init(&build).parse_input(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::builtin_matchers::literal::literal;
+    use crate::generic::combinators::either::either;
+    use crate::generic::combinators::left::left;
+    use crate::generic::combinators::right::right;
+    use std::cell::Cell;
+
+    /// Counts the depth of balanced parentheses, such as 2 for `(())`.
+    fn nesting<'a>() -> impl Parser<'a, usize> {
+        either(
+            right(literal("("), left(lazy(nesting), literal(")"))).map(|depth| depth + 1),
+            literal("").map(|()| 0),
+        )
+    }
+
+    #[test]
+    fn test_lazy() -> Result<(), String> {
+        let parser = nesting();
+
+        assert_eq!(parser.parse("")?, ("", 0));
+        assert_eq!(parser.parse("()")?, ("", 1));
+        assert_eq!(parser.parse("((()))!")?, ("!", 3));
+        assert_eq!(parser.parse("(()")?, ("(()", 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_builds_once() -> Result<(), String> {
+        let builds = Cell::new(0);
+        let parser = lazy(|| {
+            builds.set(builds.get() + 1);
+            literal("a")
+        });
+
+        assert_eq!(parser.parse("a")?, ("", ()));
+        assert_eq!(parser.parse("ab")?, ("b", ()));
+        assert_eq!(builds.get(), 1);
+        Ok(())
+    }
+}
@@ -17,6 +17,7 @@ mod tests {
     use crate::generic::builtin_matchers::any_char::any_char;
     use crate::generic::builtin_matchers::literal::literal;
     use crate::generic::combinators::n::n;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
 
     #[test]
     fn test_left() -> Result<(), String> {
@@ -26,7 +27,10 @@ mod tests {
         let parser = left(any_word, literal("!"));
 
         assert_eq!(parser.parse("foo!")?, ("", "foo".to_string()));
-        assert_eq!(parser.parse("baz"), Err(""));
+        assert_eq!(
+            parser.parse("baz"),
+            Err(ParseFailure::new("", vec![Expected::Literal("!")]))
+        );
         Ok(())
     }
 }
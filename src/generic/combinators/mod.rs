@@ -1,4 +1,5 @@
 pub mod either;
+pub mod lazy;
 pub mod left;
 pub mod n;
 pub mod optional;
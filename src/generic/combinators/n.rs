@@ -2,7 +2,7 @@ use std::iter::Iterator;
 use std::ops::Bound::{Excluded, Included, Unbounded};
 use std::ops::RangeBounds;
 
-use crate::generic::combinators::parser::Parser;
+use crate::generic::combinators::parser::{merge_failures, Parsed, Parser};
 
 pub fn n<'a, P, R, A>(parser: P, range: R) -> impl Parser<'a, Vec<A>>
 where
@@ -11,6 +11,7 @@ where
 {
     move |mut input| {
         let mut result = Vec::new();
+        let mut furthest = None;
 
         let start = match range.start_bound() {
             Unbounded => 0,
@@ -26,17 +27,24 @@ where
         };
 
         for num in 0..end {
-            if let Ok((next_input, next_result)) = parser.parse(input) {
-                input = next_input;
-                result.push(next_result);
-            } else if num < start {
-                return Err(input);
-            } else {
-                break;
+            match parser.parse_input(input) {
+                Ok(parsed) => {
+                    input = parsed.remaining;
+                    result.push(parsed.output);
+                    furthest = match parsed.furthest {
+                        Some(later) => Some(merge_failures(furthest, later)),
+                        None => furthest,
+                    };
+                }
+                Err(failure) if num < start => return Err(merge_failures(furthest, failure)),
+                Err(failure) => {
+                    furthest = Some(merge_failures(furthest, failure));
+                    break;
+                }
             }
         }
 
-        Ok((input, result))
+        Ok(Parsed::new(input, result).recovered_from(furthest))
     }
 }
 
@@ -44,6 +52,10 @@ where
 mod tests {
     use super::*;
     use crate::generic::builtin_matchers::literal::literal;
+    use crate::generic::combinators::either::either;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
+    use crate::generic::combinators::right::right;
+    use crate::generic::input::Input;
 
     #[test]
     fn test_n_or_more() -> Result<(), String> {
@@ -51,9 +63,18 @@ mod tests {
 
         assert_eq!(parser.parse("foofoofoofoo")?, ("", vec![(), (), (), ()]));
         assert_eq!(parser.parse("foofoofoo")?, ("", vec![(), (), ()]));
-        assert_eq!(parser.parse("foofoo"), Err(""));
-        assert_eq!(parser.parse("bar"), Err("bar"));
-        assert_eq!(parser.parse(""), Err(""));
+        assert_eq!(
+            parser.parse("foofoo"),
+            Err(ParseFailure::new("", vec![Expected::Literal("foo")]))
+        );
+        assert_eq!(
+            parser.parse("bar"),
+            Err(ParseFailure::new("bar", vec![Expected::Literal("foo")]))
+        );
+        assert_eq!(
+            parser.parse(""),
+            Err(ParseFailure::new("", vec![Expected::Literal("foo")]))
+        );
         Ok(())
     }
 
@@ -82,4 +103,30 @@ mod tests {
         assert_eq!(parser.parse("")?, ("", vec![]));
         Ok(())
     }
+
+    #[test]
+    fn test_keeps_the_furthest_failure_of_any_repetition() -> Result<(), String> {
+        let parser = n(
+            either(right(literal("aaa"), literal("x")), literal("a")),
+            0..,
+        );
+
+        let parsed = parser.parse_input(Input::new("aaab"))?;
+
+        assert_eq!(parsed.remaining.as_str(), "b");
+        assert_eq!(
+            parsed
+                .furthest
+                .map(|failure| (failure.remaining, failure.expected)),
+            Some((
+                "b",
+                vec![
+                    Expected::Literal("x"),
+                    Expected::Literal("aaa"),
+                    Expected::Literal("a")
+                ]
+            ))
+        );
+        Ok(())
+    }
 }
@@ -1,12 +1,12 @@
-use crate::generic::combinators::parser::Parser;
+use crate::generic::combinators::parser::{Parsed, Parser};
 
 pub fn optional<'a, P, A>(parser: P) -> impl Parser<'a, Option<A>>
 where
     P: Parser<'a, A>,
 {
-    move |input| match parser.parse(input) {
-        Ok((next_input, result)) => Ok((next_input, Some(result))),
-        Err(_) => Ok((input, None)),
+    move |input| match parser.parse_input(input) {
+        Ok(parsed) => Ok(parsed.map(Some)),
+        Err(failure) => Ok(Parsed::new(input, None).recovered_from(Some(failure))),
     }
 }
 
@@ -14,6 +14,7 @@ where
 mod tests {
     use super::*;
     use crate::generic::builtin_matchers::literal::literal;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
 
     #[test]
     fn test_optional() -> Result<(), String> {
@@ -24,4 +25,16 @@ mod tests {
         assert_eq!(parser.parse("")?, ("", None));
         Ok(())
     }
+
+    #[test]
+    fn test_optional_keeps_failure() -> Result<(), String> {
+        let parser = optional(literal("foo"));
+
+        assert_eq!(
+            parser.parse("bar")?.furthest,
+            Some(ParseFailure::new("bar", vec![Expected::Literal("foo")]))
+        );
+        assert_eq!(parser.parse("foo")?.furthest, None);
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use crate::generic::combinators::parser::Parser;
+use crate::generic::combinators::parser::{merge_failures, Parsed, Parser};
 
 pub fn pair<'a, P1, P2, R1, R2>(parser1: P1, parser2: P2) -> impl Parser<'a, (R1, R2)>
 where
@@ -6,11 +6,18 @@ where
     P2: Parser<'a, R2>,
 {
     move |input| {
-        parser1.parse(input).and_then(|(next_input, result1)| {
-            parser2
-                .parse(next_input)
-                .map(|(last_input, result2)| (last_input, (result1, result2)))
-        })
+        let Parsed {
+            remaining,
+            output: result1,
+            furthest,
+        } = parser1.parse_input(input)?;
+
+        match parser2.parse_input(remaining) {
+            Ok(parsed) => Ok(parsed
+                .map(|result2| (result1, result2))
+                .recovered_from(furthest)),
+            Err(failure) => Err(merge_failures(furthest, failure)),
+        }
     }
 }
 
@@ -20,6 +27,8 @@ mod tests {
     use crate::generic::builtin_matchers::any_char::any_char;
     use crate::generic::builtin_matchers::literal::literal;
     use crate::generic::combinators::n::n;
+    use crate::generic::combinators::optional::optional;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
 
     #[test]
     fn test_pair() -> Result<(), String> {
@@ -29,7 +38,23 @@ mod tests {
         let parser = pair(literal("foo"), any_word);
 
         assert_eq!(parser.parse("foobar")?, ("", ((), "bar".to_string())));
-        assert_eq!(parser.parse("baz"), Err("baz"));
+        assert_eq!(
+            parser.parse("baz"),
+            Err(ParseFailure::new("baz", vec![Expected::Literal("foo")]))
+        );
         Ok(())
     }
+
+    #[test]
+    fn test_pair_merges_recovered_failures() {
+        let parser = pair(optional(literal("foo")), literal("bar"));
+
+        assert_eq!(
+            parser.parse("baz"),
+            Err(ParseFailure::new(
+                "baz",
+                vec![Expected::Literal("foo"), Expected::Literal("bar")]
+            ))
+        );
+    }
 }
@@ -1,7 +1,256 @@
-pub type ParseResult<'a, Output> = Result<(&'a str, Output), &'a str>;
+use std::fmt;
+
+use crate::generic::input::Input;
+use crate::generic::span::{Span, Spanned};
+
+pub type ParseResult<'a, Output> = Result<Parsed<'a, Output>, ParseFailure<'a>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expected {
+    Literal(&'static str),
+    Label(&'static str),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Literal(literal) => write!(f, "`{}`", literal),
+            Expected::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+/// The furthest point a parser reached before failing, along with everything
+/// that would have allowed it to continue from there. `labels` point at other
+/// parts of the source that explain the failure, and `notes` suggest fixes.
+/// If the input was well formed but turned down, `rejection` says why and
+/// where; the failure is then reported there rather than at `remaining`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseFailure<'a> {
+    pub remaining: &'a str,
+    pub expected: Vec<Expected>,
+    pub labels: Vec<Spanned<String>>,
+    pub notes: Vec<String>,
+    pub rejection: Option<Spanned<String>>,
+}
+
+impl<'a> ParseFailure<'a> {
+    pub fn new(remaining: &'a str, expected: Vec<Expected>) -> Self {
+        ParseFailure {
+            remaining,
+            expected,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            rejection: None,
+        }
+    }
+
+    /// Builds a failure for input that a parser got as far as `remaining`
+    /// into before `rejection` turned it down.
+    pub fn rejected(remaining: &'a str, rejection: Rejection) -> Self {
+        ParseFailure {
+            notes: rejection.notes,
+            rejection: Some(rejection.label),
+            ..ParseFailure::new(remaining, Vec::new())
+        }
+    }
+
+    #[must_use]
+    pub fn with_label(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.labels.push(Spanned::new(label.into(), span));
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Keeps whichever failure got further into the input, combining the
+    /// expectations of both when they stopped at the same place.
+    #[must_use]
+    pub fn merge(self, other: ParseFailure<'a>) -> ParseFailure<'a> {
+        let self_len = self.remaining.len();
+        let other_len = other.remaining.len();
+
+        if self_len < other_len {
+            self
+        } else if other_len < self_len {
+            other
+        } else {
+            ParseFailure {
+                remaining: other.remaining,
+                expected: union(self.expected, other.expected),
+                labels: union(self.labels, other.labels),
+                notes: union(self.notes, other.notes),
+                rejection: self.rejection.or(other.rejection),
+            }
+        }
+    }
+}
+
+fn union<T: PartialEq>(mut items: Vec<T>, others: Vec<T>) -> Vec<T> {
+    for other in others {
+        if !items.contains(&other) {
+            items.push(other);
+        }
+    }
+
+    items
+}
+
+/// Why a parser turned down input that was otherwise well formed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub label: Spanned<String>,
+    pub notes: Vec<String>,
+}
+
+impl Rejection {
+    pub fn new(span: Span, label: impl Into<String>) -> Self {
+        Rejection {
+            label: Spanned::new(label.into(), span),
+            notes: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl fmt::Display for ParseFailure<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected {} at {:?}",
+            format_expected(&self.expected),
+            self.remaining
+        )
+    }
+}
+
+impl From<ParseFailure<'_>> for String {
+    fn from(failure: ParseFailure<'_>) -> Self {
+        failure.to_string()
+    }
+}
+
+/// Folds an earlier failure that a parser recovered from into a later one.
+#[must_use]
+pub fn merge_failures<'a>(
+    earlier: Option<ParseFailure<'a>>,
+    later: ParseFailure<'a>,
+) -> ParseFailure<'a> {
+    match earlier {
+        Some(earlier) => earlier.merge(later),
+        None => later,
+    }
+}
+
+/// Rejects the first of `items` whose key is that of an earlier item, at
+/// the key's span and with `message` built from the key, for lists in which
+/// each name or kind of setting may only be given once.
+pub fn reject_duplicates<'i, T, K, F, M>(
+    items: &'i [T],
+    key: F,
+    message: M,
+) -> Result<(), Rejection>
+where
+    K: PartialEq,
+    F: Fn(&'i T) -> Spanned<K>,
+    M: Fn(&K) -> String,
+{
+    let keys: Vec<Spanned<K>> = items.iter().map(key).collect();
+
+    for (index, item) in keys.iter().enumerate() {
+        if keys[..index]
+            .iter()
+            .any(|earlier| earlier.value == item.value)
+        {
+            return Err(Rejection::new(item.span, message(&item.value)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Joins expectations into a list such as "`;`, `?` or `:`".
+pub fn format_expected<T: fmt::Display>(expected: &[T]) -> String {
+    match expected.split_last() {
+        None => "nothing".to_string(),
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => {
+            let rest = rest
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            format!("{} or {}", rest, last)
+        }
+    }
+}
+
+/// A successful parse. `furthest` holds the furthest failure the parser
+/// recovered from on the way, e.g. an `optional` branch that did not match,
+/// so that a failure further along can still report it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parsed<'a, Output> {
+    pub remaining: Input<'a>,
+    pub output: Output,
+    pub furthest: Option<ParseFailure<'a>>,
+}
+
+impl<'a, Output> Parsed<'a, Output> {
+    pub fn new(remaining: Input<'a>, output: Output) -> Self {
+        Parsed {
+            remaining,
+            output,
+            furthest: None,
+        }
+    }
+
+    pub fn map<F, NewOutput>(self, map_fn: F) -> Parsed<'a, NewOutput>
+    where
+        F: FnOnce(Output) -> NewOutput,
+    {
+        Parsed {
+            remaining: self.remaining,
+            output: map_fn(self.output),
+            furthest: self.furthest,
+        }
+    }
+
+    #[must_use]
+    pub fn recovered_from(self, earlier: Option<ParseFailure<'a>>) -> Self {
+        let furthest = match (earlier, self.furthest) {
+            (Some(earlier), Some(later)) => Some(earlier.merge(later)),
+            (earlier, later) => earlier.or(later),
+        };
+
+        Parsed { furthest, ..self }
+    }
+}
+
+/// Compares against a `(remaining, output)` pair, which keeps assertions on
+/// parser results terse.
+impl<'a, 'b, Output: PartialEq> PartialEq<(&'b str, Output)> for Parsed<'a, Output> {
+    fn eq(&self, (remaining, output): &(&'b str, Output)) -> bool {
+        self.remaining.as_str() == *remaining && self.output == *output
+    }
+}
 
 pub trait Parser<'a, Output> {
-    fn parse(&self, input: &'a str) -> ParseResult<'a, Output>;
+    fn parse_input(&self, input: Input<'a>) -> ParseResult<'a, Output>;
+
+    /// Parses from the start of `source`.
+    fn parse(&self, source: &'a str) -> ParseResult<'a, Output> {
+        self.parse_input(Input::new(source))
+    }
 
     fn and_then<F, NextParser, NewOutput>(self, f: F) -> BoxedParser<'a, NewOutput>
     where
@@ -32,13 +281,32 @@ pub trait Parser<'a, Output> {
     {
         BoxedParser::new(pred(self, pred_fn))
     }
+
+    /// Fails with the rejection `validate_fn` returns, if any, for this
+    /// parser's output.
+    fn validate<F>(self, validate_fn: F) -> BoxedParser<'a, Output>
+    where
+        Self: Sized + 'a,
+        Output: 'a,
+        F: Fn(&Output) -> Result<(), Rejection> + 'a,
+    {
+        BoxedParser::new(validate(self, validate_fn))
+    }
+
+    fn spanned(self) -> BoxedParser<'a, Spanned<Output>>
+    where
+        Self: Sized + 'a,
+        Output: 'a,
+    {
+        BoxedParser::new(spanned(self))
+    }
 }
 
 impl<'a, F, Output> Parser<'a, Output> for F
 where
-    F: Fn(&'a str) -> ParseResult<Output>,
+    F: Fn(Input<'a>) -> ParseResult<'a, Output>,
 {
-    fn parse(&self, input: &'a str) -> ParseResult<'a, Output> {
+    fn parse_input(&self, input: Input<'a>) -> ParseResult<'a, Output> {
         self(input)
     }
 }
@@ -59,8 +327,8 @@ impl<'a, Output> BoxedParser<'a, Output> {
 }
 
 impl<'a, Output> Parser<'a, Output> for BoxedParser<'a, Output> {
-    fn parse(&self, input: &'a str) -> ParseResult<'a, Output> {
-        self.parser.parse(input)
+    fn parse_input(&self, input: Input<'a>) -> ParseResult<'a, Output> {
+        self.parser.parse_input(input)
     }
 }
 
@@ -69,11 +337,7 @@ where
     P: Parser<'a, A>,
     F: Fn(A) -> B,
 {
-    move |input| {
-        parser
-            .parse(input)
-            .map(|(next_input, result)| (next_input, map_fn(result)))
-    }
+    move |input| parser.parse_input(input).map(|parsed| parsed.map(&map_fn))
 }
 
 fn and_then<'a, P, F, A, B, NextP>(parser: P, f: F) -> impl Parser<'a, B>
@@ -82,9 +346,17 @@ where
     NextP: Parser<'a, B>,
     F: Fn(A) -> NextP,
 {
-    move |input| match parser.parse(input) {
-        Ok((next_input, result)) => f(result).parse(next_input),
-        Err(err) => Err(err),
+    move |input| {
+        let Parsed {
+            remaining,
+            output,
+            furthest,
+        } = parser.parse_input(input)?;
+
+        match f(output).parse_input(remaining) {
+            Ok(parsed) => Ok(parsed.recovered_from(furthest)),
+            Err(failure) => Err(merge_failures(furthest, failure)),
+        }
     }
 }
 
@@ -94,13 +366,44 @@ where
     F: Fn(&A) -> bool,
 {
     move |input| {
-        if let Ok((next_input, value)) = parser.parse(input) {
-            if predicate(&value) {
-                return Ok((next_input, value));
+        if let Ok(parsed) = parser.parse_input(input) {
+            if predicate(&parsed.output) {
+                return Ok(parsed);
             }
         }
 
-        Err(input)
+        Err(ParseFailure::new(input.as_str(), Vec::new()))
+    }
+}
+
+fn validate<'a, P, A, F>(parser: P, validate_fn: F) -> impl Parser<'a, A>
+where
+    P: Parser<'a, A>,
+    F: Fn(&A) -> Result<(), Rejection>,
+{
+    move |input| {
+        let parsed = parser.parse_input(input)?;
+
+        match validate_fn(&parsed.output) {
+            Ok(()) => Ok(parsed),
+            Err(rejection) => Err(merge_failures(
+                parsed.furthest,
+                ParseFailure::rejected(parsed.remaining.as_str(), rejection),
+            )),
+        }
+    }
+}
+
+fn spanned<'a, P, A>(parser: P) -> impl Parser<'a, Spanned<A>>
+where
+    P: Parser<'a, A>,
+{
+    move |input: Input<'a>| {
+        parser.parse_input(input).map(|parsed| {
+            let span = input.span_to(parsed.remaining);
+
+            parsed.map(|value| Spanned::new(value, span))
+        })
     }
 }
 
@@ -111,6 +414,7 @@ mod tests {
     use crate::generic::builtin_matchers::literal::literal;
     use crate::generic::combinators::left::left;
     use crate::generic::combinators::n::n;
+    use crate::generic::span::spanned;
 
     #[test]
     fn test_map() -> Result<(), String> {
@@ -137,9 +441,51 @@ mod tests {
 
         assert_eq!(parser.parse("foo!bar")?, ("", ()));
         assert_eq!(parser.parse("bar!baz")?, ("", ()));
-        assert_eq!(parser.parse("foo!baz"), Err("baz"));
-        assert_eq!(parser.parse("bar!bar"), Err("bar"));
-        assert_eq!(parser.parse(""), Err(""));
+        assert_eq!(
+            parser.parse("foo!baz"),
+            Err(ParseFailure::new("baz", vec![Expected::Literal("bar")]))
+        );
+        assert_eq!(
+            parser.parse("bar!bar"),
+            Err(ParseFailure::new("bar", vec![Expected::Literal("baz")]))
+        );
+        assert_eq!(parser.parse(""), Err(ParseFailure::new("", Vec::new())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_spanned() -> Result<(), String> {
+        let parser = left(literal("foo"), literal(" ")).and_then(|_| literal("bar").spanned());
+
+        assert_eq!(
+            parser.parse("foo bar!")?,
+            ("!", Spanned::new((), Span::new(4, 7)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<(), String> {
+        let alphanumeric = any_char.pred(|c| c.is_alphanumeric());
+        let parser = n(alphanumeric, 0..).spanned().validate(|word| {
+            if word.value.len() < 2 {
+                Err(Rejection::new(word.span, "too short").with_note("use two characters"))
+            } else {
+                Ok(())
+            }
+        });
+
+        assert_eq!(
+            parser.parse("ab!")?,
+            ("!", Spanned::new(vec!['a', 'b'], Span::new(0, 2)))
+        );
+        assert_eq!(
+            parser.parse("a!"),
+            Err(ParseFailure::rejected(
+                "!",
+                Rejection::new(Span::new(0, 1), "too short").with_note("use two characters")
+            ))
+        );
         Ok(())
     }
 
@@ -148,7 +494,68 @@ mod tests {
         let parser = pred(any_char, |c| *c == 'f');
 
         assert_eq!(parser.parse("foo")?, ("oo", 'f'));
-        assert_eq!(parser.parse("bar"), Err("bar"));
+        assert_eq!(
+            parser.parse("bar"),
+            Err(ParseFailure::new("bar", Vec::new()))
+        );
         Ok(())
     }
+
+    #[test]
+    fn test_merge_keeps_furthest_failure() {
+        let near = ParseFailure::new("bar baz", vec![Expected::Literal("?")]);
+        let far = ParseFailure::new("baz", vec![Expected::Literal(";")]);
+
+        assert_eq!(near.clone().merge(far.clone()), far);
+        assert_eq!(far.clone().merge(near), far);
+    }
+
+    #[test]
+    fn test_reject_duplicates() {
+        let names = [
+            spanned("a", 0),
+            spanned("b", 2),
+            spanned("a", 4),
+            spanned("b", 6),
+        ];
+        let key = |name: &Spanned<String>| name.clone();
+        let message = |name: &String| format!("`{}` again", name);
+
+        assert_eq!(
+            reject_duplicates(&names, key, message),
+            Err(Rejection::new(Span::new(4, 5), "`a` again"))
+        );
+        assert_eq!(reject_duplicates(&names[..2], key, message), Ok(()));
+    }
+
+    #[test]
+    fn test_merge_combines_failures_at_same_position() {
+        let first = ParseFailure::new("]", vec![Expected::Literal("?"), Expected::Literal(":")]);
+        let second = ParseFailure::new("]", vec![Expected::Literal(":"), Expected::Literal(";")]);
+
+        assert_eq!(
+            first.merge(second),
+            ParseFailure::new(
+                "]",
+                vec![
+                    Expected::Literal("?"),
+                    Expected::Literal(":"),
+                    Expected::Literal(";")
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_expected() {
+        assert_eq!(format_expected(&[Expected::Literal(";")]), "`;`");
+        assert_eq!(
+            format_expected(&[
+                Expected::Literal(";"),
+                Expected::Literal("?"),
+                Expected::Label("process name")
+            ]),
+            "`;`, `?` or process name"
+        );
+    }
 }
@@ -17,6 +17,7 @@ mod tests {
     use crate::generic::builtin_matchers::any_char::any_char;
     use crate::generic::builtin_matchers::literal::literal;
     use crate::generic::combinators::n::n;
+    use crate::generic::combinators::parser::{Expected, ParseFailure};
 
     #[test]
     fn test_right() -> Result<(), String> {
@@ -26,7 +27,10 @@ mod tests {
         let parser = right(literal("foo"), any_word);
 
         assert_eq!(parser.parse("foobar")?, ("", "bar".to_string()));
-        assert_eq!(parser.parse("baz"), Err("baz"));
+        assert_eq!(
+            parser.parse("baz"),
+            Err(ParseFailure::new("baz", vec![Expected::Literal("foo")]))
+        );
         Ok(())
     }
 }
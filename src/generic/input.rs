@@ -0,0 +1,58 @@
+use crate::generic::span::Span;
+
+/// The unparsed rest of a source, along with its byte offset into that
+/// source so that parsers can report where their output came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Input<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Input<'a> {
+    #[must_use]
+    pub fn new(source: &'a str) -> Self {
+        Input { source, offset: 0 }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &'a str {
+        &self.source[self.offset..]
+    }
+
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Moves past the next `len` bytes, which must end on a character
+    /// boundary.
+    #[must_use]
+    pub fn advance(&self, len: usize) -> Self {
+        Input {
+            source: self.source,
+            offset: self.offset + len,
+        }
+    }
+
+    /// Returns the span from this input up to a later point in the same
+    /// source.
+    #[must_use]
+    pub fn span_to(&self, end: Input<'a>) -> Span {
+        Span::new(self.offset, end.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let input = Input::new("é ? loop1;");
+        let next = input.advance(2);
+
+        assert_eq!(next.as_str(), " ? loop1;");
+        assert_eq!(next.offset(), 2);
+        assert_eq!(input.span_to(next.advance(3)), Span::new(0, 5));
+    }
+}
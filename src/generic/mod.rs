@@ -1,2 +1,4 @@
 pub mod builtin_matchers;
 pub mod combinators;
+pub mod input;
+pub mod span;
@@ -1,43 +1,156 @@
+// Parse errors carry their source positions, labels and notes by value; they
+// are only built once per failed parse, so their size is not worth boxing.
+#![allow(clippy::result_large_err)]
+
 pub mod arpx_job;
+mod diagnostic;
 mod error;
 mod generic;
+mod line_index;
+mod lint;
+mod loader;
 
 mod prelude {
-    pub use crate::generic::builtin_matchers::any_char::any_char;
     pub use crate::generic::builtin_matchers::literal::literal;
-    pub use crate::generic::builtin_matchers::whitespace::whitespace_wrap;
+    pub use crate::generic::builtin_matchers::whitespace::{past_trivia, space0, whitespace_wrap};
     pub use crate::generic::combinators::either::either;
     pub use crate::generic::combinators::left::left;
     pub use crate::generic::combinators::n::n;
     pub use crate::generic::combinators::optional::optional;
     pub use crate::generic::combinators::pair::pair;
-    pub use crate::generic::combinators::parser::{ParseResult, Parser};
+    pub use crate::generic::combinators::parser::{
+        reject_duplicates, ParseResult, Parsed, Parser, Rejection,
+    };
     pub use crate::generic::combinators::right::right;
+    pub use crate::generic::input::Input;
+    pub use crate::generic::span::{Span, Spanned};
 }
 
-pub use arpx_job::{Job, Process, Task};
-pub use generic::combinators::parser::{ParseResult, Parser};
+pub use arpx_job::{
+    format_name, Arg, Backoff, Branch, Concurrency, Condition, EvaluationError, ExitCodes,
+    FailurePolicy, Forever, Group, Handler, Include, Invocation, Job, Macro, Mode, Number, Operand,
+    Process, Repeat, Retry, Task, Value, Variable, When,
+};
+pub use diagnostic::{Diagnostic, Renderer, Severity};
+pub use error::{ParseError, ParseErrorKind};
+pub use generic::builtin_matchers::whitespace::Comment;
+pub use generic::combinators::parser::{
+    Expected, ParseFailure, ParseResult, Parsed, Parser, Rejection,
+};
+pub use generic::input::Input;
+pub use generic::span::{Span, Spanned};
+pub use line_index::{LineIndex, SourceContext, TextCoordinates};
+pub use lint::lint;
+pub use loader::{
+    load_job, load_job_with_vars, FileSystemLoader, IncludedFrom, LoadError, LoadErrorKind,
+    MemoryLoader, SourceLoader,
+};
+
+use std::collections::HashMap;
+
+use generic::builtin_matchers::whitespace::comments;
+use generic::combinators::parser::merge_failures;
 
-use error::{get_parse_error_context, ParseErrorContext};
+/// Parses a job script. Malformed input of any kind is reported as a
+/// `ParseError`; this function does not panic. Scripts that parse but could
+/// be written more simply are reported separately by `lint`, and `include`
+/// directives are left for `load_job` to resolve.
+pub fn parse_job(job: &str) -> Result<arpx_job::Job, ParseError> {
+    parse_job_with_vars(job, &HashMap::new())
+}
+
+/// Parses a job script like `parse_job`, taking the value of each variable
+/// the script refers to but does not declare from `vars`. A `let` in the
+/// script overrides the value in `vars` for everything after it.
+pub fn parse_job_with_vars(
+    job: &str,
+    vars: &HashMap<String, String>,
+) -> Result<arpx_job::Job, ParseError> {
+    let failure = match arpx_job::job().parse(job) {
+        Ok(parsed) if parsed.remaining.as_str().is_empty() => {
+            let index = LineIndex::new(job);
+            let mut output = parsed.output;
 
-pub fn parse_job(job: &str) -> Result<arpx_job::Job, ParseErrorContext> {
-    match arpx_job::job().parse(job) {
-        Ok(("", parsed_job)) => Ok(parsed_job),
-        Ok((remaining, _)) => {
-            let error_context = get_parse_error_context(job, remaining);
+            if let Some(rejection) = arpx_job::expand(&mut output, job).first() {
+                return Err(ParseError::invalid_macro_call(&index, rejection));
+            }
 
-            Err(error_context)
+            return match arpx_job::interpolate(&mut output, job, vars).first() {
+                Some(&undefined) => Err(ParseError::undefined_variable(&index, undefined)),
+                None => Ok(output),
+            };
         }
-        Err(error) => panic!("{:?}", error),
-    }
+        Ok(parsed) => merge_failures(
+            parsed.furthest,
+            ParseFailure::new(parsed.remaining.as_str(), Vec::new()),
+        ),
+        Err(failure) => failure,
+    };
+
+    Err(ParseError::from_failure(&LineIndex::new(job), &failure))
+}
+
+/// Parses a job script like `parse_job`, also returning its comments in
+/// source order so that formatters can put them back.
+pub fn parse_job_with_comments(
+    job: &str,
+) -> Result<(arpx_job::Job, Vec<Spanned<Comment>>), ParseError> {
+    Ok((parse_job(job)?, comments(job)))
+}
+
+/// Parses a job script without stopping at the first error. Everything that
+/// could be parsed is returned as a partial `Job`, along with an error for
+/// each part that was skipped; the errors are empty only if the whole script
+/// is valid.
+pub fn parse_job_recovering(job: &str) -> (arpx_job::Job, Vec<ParseError>) {
+    parse_job_recovering_with_vars(job, &HashMap::new())
+}
+
+/// Parses a job script like `parse_job_recovering`, taking the value of each
+/// variable the script refers to but does not declare from `vars`, as
+/// `parse_job_with_vars` does.
+pub fn parse_job_recovering_with_vars(
+    job: &str,
+    vars: &HashMap<String, String>,
+) -> (arpx_job::Job, Vec<ParseError>) {
+    let index = LineIndex::new(job);
+    let (mut parsed, failures) = arpx_job::recovering_job(Input::new(job));
+    let rejections = arpx_job::expand(&mut parsed, job);
+    let undefined = arpx_job::interpolate(&mut parsed, job, vars);
+    let mut errors: Vec<ParseError> = failures
+        .iter()
+        .map(|failure| ParseError::from_failure(&index, failure))
+        .chain(
+            rejections
+                .iter()
+                .map(|rejection| ParseError::invalid_macro_call(&index, rejection)),
+        )
+        .chain(
+            undefined
+                .into_iter()
+                .map(|span| ParseError::undefined_variable(&index, span)),
+        )
+        .collect();
+
+    errors.sort_by_key(|error| error.span.start);
+
+    (parsed, errors)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_job, Job, ParseErrorContext, Process, Task};
+    use std::collections::HashMap;
+
+    use super::{
+        parse_job, parse_job_recovering, parse_job_recovering_with_vars, parse_job_with_comments,
+        parse_job_with_vars, Comment, Concurrency, Group, Handler, Job, ParseError, ParseErrorKind,
+        Process, Span, Task, TextCoordinates, Value,
+    };
+    use crate::arpx_job::process_names;
+    use crate::generic::span::spanned;
 
     #[test]
-    fn test_parse_job() -> Result<(), ParseErrorContext> {
+    fn test_parse_job() -> Result<(), ParseError> {
         let example = r#"
             [
                 loop1 ? loop2 : loop3; @monitor_1 @monitor_2
@@ -50,50 +163,731 @@ mod tests {
 
         let expected = Job {
             tasks: vec![
-                Task {
-                    processes: vec![
-                        Process {
-                            log_monitors: vec!["monitor_1".to_string(), "monitor_2".to_string()],
-                            name: "loop1".to_string(),
-                            onsucceed: Some("loop2".to_string()),
-                            onfail: Some("loop3".to_string()),
-                        },
-                        Process {
-                            log_monitors: Vec::new(),
-                            name: "loop2".to_string(),
-                            onsucceed: Some("loop3".to_string()),
-                            onfail: Some("loop4".to_string()),
-                        },
-                    ],
-                },
-                Task {
-                    processes: vec![Process {
-                        log_monitors: vec!["monitor_3".to_string()],
-                        name: "loop3".to_string(),
-                        onsucceed: Some("loop4".to_string()),
-                        onfail: Some("loop5".to_string()),
-                    }],
-                },
-                Task {
-                    processes: vec![Process {
-                        log_monitors: Vec::new(),
-                        name: "loop6".to_string(),
-                        onsucceed: None,
-                        onfail: None,
-                    }],
-                },
-                Task {
-                    processes: vec![Process {
-                        log_monitors: vec!["monitor_4".to_string()],
-                        name: "loop7".to_string(),
-                        onsucceed: Some("loop8".to_string()),
-                        onfail: None,
-                    }],
-                },
+                Task::Concurrent(
+                    Group {
+                        tasks: vec![
+                            Task::Process(Process {
+                                always: None,
+                                args: Vec::new(),
+                                branches: Vec::new(),
+                                condition: None,
+                                log_monitors: vec![
+                                    spanned("monitor_1", 55),
+                                    spanned("monitor_2", 66),
+                                ],
+                                name: spanned("loop1", 31),
+                                onsucceed: Some(Handler::Process(spanned("loop2", 39), Vec::new())),
+                                onfail: Some(Handler::Process(spanned("loop3", 47), Vec::new())),
+                                ontimeout: None,
+                                retry: None,
+                                span: Span::new(31, 75),
+                                timeout: None,
+                            }),
+                            Task::Process(Process {
+                                always: None,
+                                args: Vec::new(),
+                                branches: Vec::new(),
+                                condition: None,
+                                log_monitors: Vec::new(),
+                                name: spanned("loop2", 92),
+                                onsucceed: Some(Handler::Process(
+                                    spanned("loop3", 100),
+                                    Vec::new(),
+                                )),
+                                onfail: Some(Handler::Process(spanned("loop4", 108), Vec::new())),
+                                ontimeout: None,
+                                retry: None,
+                                span: Span::new(92, 114),
+                                timeout: None,
+                            }),
+                        ],
+                        span: Span::new(13, 128),
+                    },
+                    Concurrency::default(),
+                ),
+                Task::Process(Process {
+                    always: None,
+                    args: Vec::new(),
+                    branches: Vec::new(),
+                    condition: None,
+                    log_monitors: vec![spanned("monitor_3", 165)],
+                    name: spanned("loop3", 141),
+                    onsucceed: Some(Handler::Process(spanned("loop4", 149), Vec::new())),
+                    onfail: Some(Handler::Process(spanned("loop5", 157), Vec::new())),
+                    ontimeout: None,
+                    retry: None,
+                    span: Span::new(141, 174),
+                    timeout: None,
+                }),
+                Task::Process(Process {
+                    always: None,
+                    args: Vec::new(),
+                    branches: Vec::new(),
+                    condition: None,
+                    log_monitors: Vec::new(),
+                    name: spanned("loop6", 187),
+                    onsucceed: None,
+                    onfail: None,
+                    ontimeout: None,
+                    retry: None,
+                    span: Span::new(187, 193),
+                    timeout: None,
+                }),
+                Task::Process(Process {
+                    always: None,
+                    args: Vec::new(),
+                    branches: Vec::new(),
+                    condition: None,
+                    log_monitors: vec![spanned("monitor_4", 222)],
+                    name: spanned("loop7", 206),
+                    onsucceed: Some(Handler::Process(spanned("loop8", 214), Vec::new())),
+                    onfail: None,
+                    ontimeout: None,
+                    retry: None,
+                    span: Span::new(206, 231),
+                    timeout: None,
+                }),
             ],
+            span: Span::new(0, 240),
+            variables: Vec::new(),
+            macros: Vec::new(),
         };
 
         assert_eq!(parse_job(example)?, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_parse_job_error() {
+        let example = r#"
+            loop1 ? loop2;
+            loop3 ] loop4;
+        "#;
+
+        let error = parse_job(example).unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(error.found, Some("]".to_string()));
+        assert_eq!(error.span, Span::new(46, 47));
+        assert_eq!(
+            error.start,
+            TextCoordinates {
+                line: 3,
+                column: 18,
+                utf8_column: 18,
+                utf16_column: 18,
+            }
+        );
+        assert_eq!(
+            error.end,
+            TextCoordinates {
+                line: 3,
+                column: 19,
+                utf8_column: 19,
+                utf16_column: 19,
+            }
+        );
+        assert_eq!(
+            error.expected,
+            vec![
+                "`(`",
+                "`retry`",
+                "`timeout`",
+                "`?`",
+                "`:`",
+                "`~`",
+                "`!`",
+                "`;`"
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "unexpected `]` at line 3, column 19, expected `(`, `retry`, `timeout`, `?`, `:`, `~`, `!` or `;`"
+        );
+    }
+
+    #[test]
+    fn test_parse_job_error_skips_whitespace() {
+        let error = parse_job("\n  ]").unwrap_err();
+
+        assert_eq!(error.found, Some("]".to_string()));
+        assert_eq!((error.start.line, error.start.column), (2, 2));
+    }
+
+    #[test]
+    fn test_parse_job_recovering() {
+        let example = r#"
+            loop1 ? loop2;
+            loop3 ] loop4;
+            loop5 ~ alert;
+            loop6 ? ? loop7;
+            loop8;
+        "#;
+
+        let (job, errors) = parse_job_recovering(example);
+        let names = process_names(&job.tasks);
+        let positions: Vec<(u32, u32)> = errors
+            .iter()
+            .map(|error| (error.start.line, error.start.column))
+            .collect();
+
+        assert_eq!(names, vec!["loop1", "loop4", "loop8"]);
+        assert_eq!(positions, vec![(3, 18), (4, 20), (5, 20)]);
+        assert_eq!(errors[1].kind, ParseErrorKind::Invalid);
+        assert_eq!(
+            errors[1].message(),
+            "this handler never runs because the process has no timeout"
+        );
+        assert_eq!(job.span, Span::new(0, example.len()));
+    }
+
+    #[test]
+    fn test_parse_job_recovering_matches_parse_job() -> Result<(), ParseError> {
+        let example = "loop1 ? loop2; [loop3; loop4;] loop5; @monitor";
+
+        assert_eq!(
+            parse_job_recovering(example),
+            (parse_job(example)?, Vec::new())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_recovering_with_vars() -> Result<(), ParseError> {
+        let example = r#"deploy(env="${env}");"#;
+        let vars = HashMap::from([("env".to_string(), "prod".to_string())]);
+
+        assert_eq!(
+            parse_job_recovering(example).1[0].kind,
+            ParseErrorKind::UndefinedVariable
+        );
+        assert_eq!(
+            parse_job_recovering_with_vars(example, &vars),
+            (parse_job_with_vars(example, &vars)?, Vec::new())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_with_vars() -> Result<(), ParseError> {
+        let example = r#"
+            let region = "us-east-1";
+            deploy(region="${region}", env="${env}") ? "notify-${env}";
+            let region = "eu-${region}";
+            "sync ${region}";
+        "#;
+        let vars = HashMap::from([
+            ("env".to_string(), "staging".to_string()),
+            ("region".to_string(), "ignored".to_string()),
+        ]);
+
+        let job = parse_job_with_vars(example, &vars)?;
+        let deploy = job.tasks[0].processes()[0];
+        let values: Vec<&Value> = deploy.args.iter().map(|arg| &arg.value.value).collect();
+
+        assert_eq!(
+            values,
+            vec![
+                &Value::String("us-east-1".to_string()),
+                &Value::String("staging".to_string()),
+            ]
+        );
+        assert!(matches!(
+            &deploy.onsucceed,
+            Some(Handler::Process(name, _)) if name.value == "notify-staging"
+        ));
+        assert_eq!(job.tasks[1].processes()[0].name.value, "sync eu-us-east-1");
+        assert_eq!(job.variables[1].value.value, "eu-us-east-1");
+        assert_eq!(job.variables[1].name.span, Span::new(127, 133));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_undefined_variable() {
+        let example = "build;\n\"deploy ${region}\";\nlet region = \"us-east-1\";";
+
+        let error = parse_job(example).unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::UndefinedVariable);
+        assert_eq!(error.span, Span::new(15, 24));
+        assert_eq!(
+            error.to_string(),
+            "`${region}` is not defined at line 2, column 9"
+        );
+        assert_eq!(parse_job_recovering(example).1, vec![error]);
+        assert_eq!(
+            parse_job("deploy(region);").unwrap_err().message(),
+            "`region` is not defined"
+        );
+    }
+
+    #[test]
+    fn test_parse_job_bare_references() -> Result<(), ParseError> {
+        let job = parse_job("let region = \"eu\"; deploy(region, zone=region);")?;
+        let values: Vec<String> = job.tasks[0].processes()[0]
+            .args
+            .iter()
+            .map(|arg| arg.value.value.to_string())
+            .collect();
+
+        assert_eq!(values, vec!["\"eu\"", "\"eu\""]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_recovering_reports_undefined_variables_in_order() {
+        let example = r#""a${x}"; b ] c; "d${y}";"#;
+
+        let errors = parse_job_recovering(example).1;
+        let kinds: Vec<ParseErrorKind> = errors.iter().map(|error| error.kind).collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                ParseErrorKind::UndefinedVariable,
+                ParseErrorKind::UnexpectedToken,
+                ParseErrorKind::UndefinedVariable,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_job_with_macros() -> Result<(), ParseError> {
+        let example = r#"
+            def ci(x) { build(x) : "page ${x}"; test(x); }
+            ci(x="web");
+            ci(x=2);
+        "#;
+
+        let job = parse_job(example)?;
+        let Task::Invocation(web) = &job.tasks[0] else {
+            panic!("expected a macro call, found {:?}", job.tasks[0]);
+        };
+        let build = job.tasks[1].processes()[0];
+
+        assert_eq!(web.name.value, "ci");
+        assert_eq!(web.definition, job.macros[0].span);
+        assert_eq!(
+            web.tasks[0].processes()[0].args[0].value.value,
+            Value::String("web".to_string())
+        );
+        assert_eq!(build.args[0].value.value.to_string(), "2");
+        assert!(matches!(
+            &build.onfail,
+            Some(Handler::Process(name, _)) if name.value == "page 2"
+        ));
+        assert_eq!(build.span, web.tasks[0].span());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_with_macro_handler_args() -> Result<(), ParseError> {
+        let example = r#"def ci(x) { build(x) ? test(x) : page; } ci(x="web");"#;
+
+        let job = parse_job(example)?;
+        let build = job.tasks[0].processes()[0];
+        let Some(Handler::Process(name, args)) = &build.onsucceed else {
+            panic!("expected a process handler, found {:?}", build.onsucceed);
+        };
+
+        assert_eq!(name.value, "test");
+        assert_eq!(args[0].value.value, Value::String("web".to_string()));
+        assert!(matches!(
+            &build.onfail,
+            Some(Handler::Process(name, args)) if name.value == "page" && args.is_empty()
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_invalid_macro_call() {
+        let example = "def ci(x) { build(x); }\nci(x=1, y=2);";
+
+        let error = parse_job(example).unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::InvalidMacroCall);
+        assert_eq!(error.span, Span::new(32, 33));
+        assert_eq!(
+            error.to_string(),
+            "`ci` has no parameter named `y` at line 2, column 9"
+        );
+        assert_eq!(parse_job_recovering(example).1[0], error);
+    }
+
+    #[test]
+    fn test_parse_job_with_loops() -> Result<(), ParseError> {
+        let example = r#"
+            repeat 3 { soak; }
+            [repeat until success max 5 { flaky; } forever { heartbeat; }]
+            repeat; forever(x=1);
+        "#;
+
+        let job = parse_job(example)?;
+        let names = process_names(&job.tasks);
+
+        assert_eq!(
+            names,
+            vec!["soak", "flaky", "heartbeat", "repeat", "forever"]
+        );
+        assert!(matches!(&job.tasks[0], Task::Repeat(repeat) if repeat.count.value == 3));
+        assert!(matches!(
+            &job.tasks[1],
+            Task::Concurrent(group, _) if matches!(&group.tasks[0], Task::Repeat(repeat) if repeat.until_success)
+                && matches!(&group.tasks[1], Task::Forever(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_rejections() {
+        for (example, span, message) in [
+            (
+                "deploy(env=1, env=2);",
+                Span::new(14, 17),
+                "`env` is already given",
+            ),
+            (
+                "a ?9..3 b;",
+                Span::new(3, 7),
+                "this range of exit codes is empty",
+            ),
+            (
+                "a; b;\nc ? \"\";",
+                Span::new(10, 12),
+                "process names cannot be empty",
+            ),
+        ] {
+            let error = parse_job(example).unwrap_err();
+
+            assert_eq!(error.kind, ParseErrorKind::Invalid);
+            assert_eq!(error.span, span);
+            assert_eq!(error.message(), message);
+            assert!(error.expected.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parse_job_loop_bounds() {
+        let error = parse_job("repeat until success max 0 { a; }").unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::Invalid);
+        assert_eq!(error.span, Span::new(25, 26));
+        assert_eq!(
+            error.to_string(),
+            "a loop must run its tasks at least once at line 1, column 26"
+        );
+    }
+
+    #[test]
+    fn test_parse_job_with_conditions() -> Result<(), ParseError> {
+        let example = r#"
+            let main = "main";
+            when env.CI == "true" { upload; }
+            deploy; when branch == "${main}";
+            notify; when !(branch == "main") { page; }
+            when(x=1);
+        "#;
+        let vars = HashMap::from([
+            ("env.CI".to_string(), "true".to_string()),
+            ("branch".to_string(), "main".to_string()),
+        ]);
+
+        let job = parse_job(example)?;
+        let Task::When(upload) = &job.tasks[0] else {
+            panic!("expected a `when` block, found {:?}", job.tasks[0]);
+        };
+        let deploy = job.tasks[1].processes()[0];
+        let Task::When(page) = &job.tasks[3] else {
+            panic!("expected a `when` block, found {:?}", job.tasks[3]);
+        };
+
+        assert_eq!(upload.condition.value.evaluate(&vars), Ok(true));
+        assert_eq!(
+            deploy
+                .condition
+                .as_ref()
+                .map(|condition| condition.value.evaluate(&vars)),
+            Some(Ok(true))
+        );
+        assert_eq!(job.tasks[2].processes()[0].condition, None);
+        assert_eq!(page.condition.value.evaluate(&vars), Ok(false));
+        assert_eq!(job.tasks[4].processes()[0].name.value, "when");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_condition_forms() -> Result<(), ParseError> {
+        let clause = parse_job("a; when b == \"c\";\n\n# later\n{ cleanup; }")?;
+
+        assert!(clause.tasks[0].processes()[0].condition.is_some());
+        assert!(matches!(&clause.tasks[1], Task::Sequential(_)));
+
+        let block = parse_job("a; when b == \"c\"\n\n# later\n{ cleanup; }")?;
+
+        assert_eq!(block.tasks[0].processes()[0].condition, None);
+        assert!(matches!(&block.tasks[1], Task::When(when) if when.tasks.len() == 1));
+        assert!(
+            parse_job("deploy; when x == \"y\";")?.tasks[0].processes()[0]
+                .condition
+                .is_some()
+        );
+
+        let last = parse_job(r#"deploy; when branch == "main""#)?;
+        let vars = HashMap::from([("branch".to_string(), "main".to_string())]);
+
+        assert_eq!(
+            last.tasks[0].processes()[0]
+                .condition
+                .as_ref()
+                .map(|condition| condition.value.evaluate(&vars)),
+            Some(Ok(true))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_conditions_see_let() -> Result<(), ParseError> {
+        let job = parse_job(
+            r#"let branch = "main"; when branch == "main" { a; } b; when branch != "main""#,
+        )?;
+        let Task::When(when) = &job.tasks[0] else {
+            panic!("expected a `when` block, found {:?}", job.tasks[0]);
+        };
+        let b = job.tasks[1].processes()[0];
+
+        assert_eq!(when.condition.value.evaluate(&HashMap::new()), Ok(true));
+        assert_eq!(
+            b.condition
+                .as_ref()
+                .map(|condition| condition.value.evaluate(&HashMap::new())),
+            Some(Ok(false))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_keywords_before_comments() -> Result<(), ParseError> {
+        let example = "let/* a */x = \"1\"; def#b\nm() { a; }\nwhen/*c*/x == \"1\" { m; }\nrepeat/**/2 { b; } forever#d\n{ c; }";
+
+        let job = parse_job(example)?;
+
+        assert_eq!(job.variables.len(), 1);
+        assert_eq!(job.macros.len(), 1);
+        assert!(
+            matches!(&job.tasks[0], Task::When(when) if matches!(when.tasks[0], Task::Invocation(_)))
+        );
+        assert!(matches!(&job.tasks[1], Task::Repeat(_)));
+        assert!(matches!(&job.tasks[2], Task::Forever(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_with_comments() -> Result<(), ParseError> {
+        let example = r#"
+            # Build everything before anything else.
+            build; /* a flaky step */ @errors
+            [
+                test-unit; # fast
+                /* [not-a-task; */ test-e2e;
+            ]
+        "#;
+
+        let (job, comments) = parse_job_with_comments(example)?;
+        let names = process_names(&job.tasks);
+        let comments: Vec<Comment> = comments.into_iter().map(|comment| comment.value).collect();
+
+        assert_eq!(names, vec!["build", "test-unit", "test-e2e"]);
+        assert_eq!(
+            job.tasks[0].processes()[0].log_monitors,
+            vec![spanned("errors", 93)]
+        );
+        assert_eq!(
+            comments,
+            vec![
+                Comment::Line(" Build everything before anything else.".to_string()),
+                Comment::Block(" a flaky step ".to_string()),
+                Comment::Line(" fast".to_string()),
+                Comment::Block(" [not-a-task; ".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_with_only_comments() -> Result<(), ParseError> {
+        assert!(parse_job("  # nothing to do\n  /* yet */\n")?
+            .tasks
+            .is_empty());
+        assert!(parse_job("")?.tasks.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_job_unterminated_comment() {
+        let error = parse_job("loop1; /* loop2;").unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::UnexpectedEndOfInput);
+        assert_eq!(error.expected, vec!["`*/`"]);
+    }
+
+    #[test]
+    fn test_parse_job_error_skips_comments() {
+        let error = parse_job("loop1 # why\n  ] loop2;").unwrap_err();
+
+        assert_eq!(error.found, Some("]".to_string()));
+        assert_eq!((error.start.line, error.start.column), (2, 2));
+        assert!(error.expected.contains(&"`;`".to_string()));
+    }
+
+    #[test]
+    fn test_parse_job_deep_nesting() {
+        let example = format!("{}loop1;", "{".repeat(10_000));
+
+        let error = parse_job(&example).unwrap_err();
+
+        assert_eq!(error.span, Span::new(32, 33));
+        assert_eq!(parse_job_recovering(&example).1, vec![error]);
+
+        let condition = format!("a; when {}a == b {{ b; }}", "(".repeat(10_000));
+
+        assert_eq!(
+            parse_job(&condition).unwrap_err().message(),
+            "conditions cannot nest more than 32 levels deep"
+        );
+    }
+
+    const MALFORMED_CORPUS: &[&str] = &[
+        "",
+        " ",
+        ";",
+        "?",
+        ":",
+        "@",
+        "[",
+        "]",
+        "[]",
+        "[[",
+        "]]",
+        "loop1",
+        "loop1 ?",
+        "loop1 ? ;",
+        "loop1 : : ;",
+        "loop1; @",
+        "loop1; @@",
+        "[loop1;",
+        "[loop1; loop2;",
+        "[loop1; loop2; ] ]",
+        "é",
+        "💥",
+        "loop1 ? é",
+        "loop1 ? loop2 💥",
+        "日本語 ? ☃ : 💥",
+        "[ 日本語 ? ☃ : 💥; ]",
+        "a\u{301};",
+        "\u{feff}loop1;",
+        "\0",
+        "loop1;\r\n]",
+        "#",
+        "/*",
+        "/* loop1; *",
+        "loop1; /* ] */ ]",
+        "[loop1; # ]\n",
+        "\"",
+        "\"loop1",
+        "\"\";",
+        "\"\\u{d800}\";",
+        "\"\\u{110000}\";",
+        "\"💥\\",
+        "loop1 ? \"a\nb\";",
+        "deploy(",
+        "deploy(env",
+        "deploy(env=",
+        "deploy(env=-);",
+        "deploy(env=1, env=2);",
+        "deploy(=1);",
+        "loop1 retry",
+        "loop1 retry(",
+        "loop1 retry(3, backoff=",
+        "loop1 retry(3, backoff=exponential(2",
+        "loop1 retry(-1);",
+        "loop1 retry(99999999999);",
+        "loop1 timeout=;",
+        "loop1 timeout=30m1h;",
+        "loop1 timeout=1ms2;",
+        "loop1 timeout=99999999999999999h;",
+        "[max=0; a; b;]",
+        "[99999999999| a; b;]",
+        "[max=2, max=3; a; b;]",
+        "repeat 0 { a; }",
+        "repeat until success max { a; }",
+        "a; when",
+        "when a == { b; }",
+        "when (a == \"b\" { c; }",
+        "loop1 ~ loop2;",
+        "{",
+        "{}",
+        "{ loop1; ]",
+        "[ loop1; }",
+        "[ { loop1; loop2; ] }",
+        "{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{",
+        "🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀🦀 ]",
+    ];
+
+    const GENERATED_FRAGMENTS: &[&str] = &[
+        "loop1", "loop-2", "_", " ", "\n", "\t", ";", "?", ":", "@", "[", "]", "é", "💥", "#",
+        "/*", "*/", "\"", "\\", "\\u{", "(", ")", "=", ",", "3", "true", "retry(", "2s",
+        "timeout=", "1h30m", "~", "{", "}", "?2", "..", "9", "!", "max=", "|", "policy=", "any|",
+        "include", "${", "}\"", "let ", "def ", "ci(x)", "repeat ", "forever", "until ", "when ",
+        "==", "!=", "&&", "||", "env.CI",
+    ];
+
+    fn assert_error_is_well_formed(input: &str, error: &ParseError) {
+        assert!(
+            input.get(error.span.start..error.span.end).is_some(),
+            "span {:?} does not fall on character boundaries of {:?}",
+            error.span,
+            input
+        );
+        assert!(error.start.line <= error.end.line);
+    }
+
+    #[test]
+    fn test_parse_job_never_panics_on_corpus() {
+        for input in MALFORMED_CORPUS {
+            if let Err(error) = parse_job(input) {
+                assert_error_is_well_formed(input, &error);
+            }
+
+            for error in parse_job_recovering(input).1 {
+                assert_error_is_well_formed(input, &error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_job_never_panics_on_generated_input() {
+        // A fixed xorshift seed keeps any failure reproducible.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+
+        for _ in 0..10_000 {
+            let len = next() % 24;
+            let input: String = (0..len)
+                .map(|_| GENERATED_FRAGMENTS[next() % GENERATED_FRAGMENTS.len()])
+                .collect();
+
+            if let Err(error) = parse_job(&input) {
+                assert_error_is_well_formed(&input, &error);
+            }
+
+            for error in parse_job_recovering(&input).1 {
+                assert_error_is_well_formed(&input, &error);
+            }
+        }
+    }
 }
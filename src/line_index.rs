@@ -0,0 +1,295 @@
+use crate::generic::span::Span;
+
+/// A position in source text. `line` is 1-based; the columns count what
+/// precedes the position on its line, in characters (`column`), UTF-8 bytes
+/// (`utf8_column`) and UTF-16 code units (`utf16_column`), so they are
+/// 0-based. Messages meant for people show `column + 1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextCoordinates {
+    pub line: u32,
+    pub column: u32,
+    pub utf8_column: u32,
+    pub utf16_column: u32,
+}
+
+/// The text surrounding a span, cut at character boundaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceContext<'a> {
+    pub before: &'a str,
+    pub text: &'a str,
+    pub after: &'a str,
+}
+
+/// Maps between byte offsets and line/column coordinates. Build one per
+/// source and reuse it for every position in that source.
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    #[must_use]
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        LineIndex {
+            source,
+            line_starts,
+        }
+    }
+
+    #[must_use]
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    #[must_use]
+    pub fn line_count(&self) -> u32 {
+        self.line_starts.len() as u32
+    }
+
+    /// Returns the coordinates of `offset`. Offsets past the end of the
+    /// source are clamped to it, and offsets inside a multi-byte character
+    /// resolve to the start of that character.
+    #[must_use]
+    pub fn coordinates(&self, offset: usize) -> TextCoordinates {
+        let offset = self.floor_char_boundary(offset);
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let prefix = &self.source[self.line_starts[line]..offset];
+
+        TextCoordinates {
+            line: line as u32 + 1,
+            column: prefix.chars().count() as u32,
+            utf8_column: prefix.len() as u32,
+            utf16_column: prefix.encode_utf16().count() as u32,
+        }
+    }
+
+    /// Returns the byte offset of a character column on a 1-based line.
+    #[must_use]
+    pub fn offset(&self, line: u32, column: u32) -> Option<usize> {
+        self.offset_by(line, column, |_| 1)
+    }
+
+    /// Returns the byte offset of a UTF-8 column on a 1-based line.
+    #[must_use]
+    pub fn offset_utf8(&self, line: u32, utf8_column: u32) -> Option<usize> {
+        self.offset_by(line, utf8_column, char::len_utf8)
+    }
+
+    /// Returns the byte offset of a UTF-16 column on a 1-based line.
+    #[must_use]
+    pub fn offset_utf16(&self, line: u32, utf16_column: u32) -> Option<usize> {
+        self.offset_by(line, utf16_column, char::len_utf16)
+    }
+
+    /// Returns the text of a 1-based line without its line ending.
+    #[must_use]
+    pub fn line_text(&self, line: u32) -> Option<&'a str> {
+        let index = (line as usize).checked_sub(1)?;
+        let start = *self.line_starts.get(index)?;
+        let end = self
+            .line_starts
+            .get(index + 1)
+            .map_or(self.source.len(), |next| next - 1);
+        let text = &self.source[start..end];
+
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// Returns `span` with up to `radius` whole characters on either side.
+    #[must_use]
+    pub fn context(&self, span: Span, radius: usize) -> SourceContext<'a> {
+        let start = self.floor_char_boundary(span.start);
+        let end = self.floor_char_boundary(span.end.max(start));
+
+        let before_start = self.source[..start]
+            .char_indices()
+            .rev()
+            .take(radius)
+            .last()
+            .map_or(start, |(index, _)| index);
+        let after_end = self.source[end..]
+            .char_indices()
+            .nth(radius)
+            .map_or(self.source.len(), |(index, _)| end + index);
+
+        SourceContext {
+            before: &self.source[before_start..start],
+            text: &self.source[start..end],
+            after: &self.source[end..after_end],
+        }
+    }
+
+    fn offset_by<F>(&self, line: u32, column: u32, width: F) -> Option<usize>
+    where
+        F: Fn(char) -> usize,
+    {
+        let text = self.line_text(line)?;
+        let start = self.line_starts[line as usize - 1];
+        let column = column as usize;
+        let mut units = 0;
+
+        for (index, c) in text.char_indices() {
+            if units >= column {
+                return (units == column).then_some(start + index);
+            }
+
+            units += width(c);
+        }
+
+        (units == column).then_some(start + text.len())
+    }
+
+    fn floor_char_boundary(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates(line: u32, column: u32, utf8_column: u32, utf16_column: u32) -> TextCoordinates {
+        TextCoordinates {
+            line,
+            column,
+            utf8_column,
+            utf16_column,
+        }
+    }
+
+    #[test]
+    fn test_coordinates() {
+        let index = LineIndex::new("loop1 ? loop2;\nloop3;\n");
+
+        assert_eq!(index.coordinates(0), coordinates(1, 0, 0, 0));
+        assert_eq!(index.coordinates(8), coordinates(1, 8, 8, 8));
+        assert_eq!(index.coordinates(14), coordinates(1, 14, 14, 14));
+        assert_eq!(index.coordinates(15), coordinates(2, 0, 0, 0));
+        assert_eq!(index.coordinates(22), coordinates(3, 0, 0, 0));
+        assert_eq!(index.coordinates(100), coordinates(3, 0, 0, 0));
+        assert_eq!(index.line_count(), 3);
+    }
+
+    #[test]
+    fn test_coordinates_with_multi_byte_characters() {
+        // `é` is 2 bytes and 1 UTF-16 unit; `💥` is 4 bytes and 2 UTF-16 units.
+        let index = LineIndex::new("é ? 💥;\n💥 loop1;");
+
+        assert_eq!(index.coordinates(2), coordinates(1, 1, 2, 1));
+        assert_eq!(index.coordinates(5), coordinates(1, 4, 5, 4));
+        assert_eq!(index.coordinates(9), coordinates(1, 5, 9, 6));
+        assert_eq!(index.coordinates(15), coordinates(2, 1, 4, 2));
+    }
+
+    #[test]
+    fn test_coordinates_inside_character() {
+        let index = LineIndex::new("a💥b");
+
+        assert_eq!(index.coordinates(2), coordinates(1, 1, 1, 1));
+        assert_eq!(index.coordinates(4), coordinates(1, 1, 1, 1));
+        assert_eq!(index.coordinates(5), coordinates(1, 2, 5, 3));
+    }
+
+    #[test]
+    fn test_offset_round_trips() {
+        let source = "é ? 💥;\r\n  日本 : b;\n";
+        let index = LineIndex::new(source);
+
+        for (offset, _) in source.char_indices() {
+            let TextCoordinates {
+                line,
+                column,
+                utf8_column,
+                utf16_column,
+            } = index.coordinates(offset);
+
+            if source[offset..].starts_with('\n') {
+                continue;
+            }
+
+            assert_eq!(index.offset(line, column), Some(offset));
+            assert_eq!(index.offset_utf8(line, utf8_column), Some(offset));
+            assert_eq!(index.offset_utf16(line, utf16_column), Some(offset));
+        }
+    }
+
+    #[test]
+    fn test_offset_out_of_range() {
+        let index = LineIndex::new("a💥b\nc");
+
+        assert_eq!(index.offset(1, 3), Some(6));
+        assert_eq!(index.offset(1, 4), None);
+        assert_eq!(index.offset_utf16(1, 2), None);
+        assert_eq!(index.offset_utf8(1, 3), None);
+        assert_eq!(index.offset(0, 0), None);
+        assert_eq!(index.offset(3, 0), None);
+    }
+
+    #[test]
+    fn test_line_text() {
+        let index = LineIndex::new("loop1;\r\nloop2;\n");
+
+        assert_eq!(index.line_text(1), Some("loop1;"));
+        assert_eq!(index.line_text(2), Some("loop2;"));
+        assert_eq!(index.line_text(3), Some(""));
+        assert_eq!(index.line_text(4), None);
+        assert_eq!(index.line_text(0), None);
+    }
+
+    #[test]
+    fn test_context() {
+        let index = LineIndex::new("loop1 ? loop2 ] loop3;");
+
+        assert_eq!(
+            index.context(Span::new(14, 15), 4),
+            SourceContext {
+                before: "op2 ",
+                text: "]",
+                after: " loo",
+            }
+        );
+        assert_eq!(
+            index.context(Span::new(0, 5), 20),
+            SourceContext {
+                before: "",
+                text: "loop1",
+                after: " ? loop2 ] loop3;",
+            }
+        );
+    }
+
+    #[test]
+    fn test_context_keeps_whole_characters() {
+        let source = "日本語日本語 ] 💥💥💥";
+        let index = LineIndex::new(source);
+        let bracket = source.find(']').unwrap();
+
+        assert_eq!(
+            index.context(Span::new(bracket, bracket + 1), 3),
+            SourceContext {
+                before: "本語 ",
+                text: "]",
+                after: " 💥💥",
+            }
+        );
+        assert_eq!(
+            index.context(Span::new(1, 5), 1),
+            SourceContext {
+                before: "",
+                text: "日",
+                after: "本",
+            }
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use crate::arpx_job::{Forever, Group, Handler, Job, Process, Repeat, Task, When};
+use crate::diagnostic::{Diagnostic, Severity};
+
+/// Reports constructs that parse but can be written more simply, as
+/// warnings in source order. Fixing them normalizes a script without
+/// changing how it runs. Included scripts are not checked, since their spans
+/// refer to other files; lint each of them on its own. Macro bodies are
+/// checked once where they are defined rather than once per call.
+#[must_use]
+pub fn lint(job: &Job) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+
+    for task in job.macros.iter().flat_map(|definition| &definition.tasks) {
+        lint_task(task, &mut warnings);
+    }
+
+    for task in &job.tasks {
+        lint_task(task, &mut warnings);
+    }
+
+    warnings.sort_by_key(|warning| warning.span.start);
+    warnings
+}
+
+fn lint_task(task: &Task, warnings: &mut Vec<Diagnostic>) {
+    match task {
+        Task::Process(process) => lint_process(process, warnings),
+        Task::Concurrent(group, _) => lint_group(group, "[", "brackets", warnings),
+        Task::Sequential(group) => lint_group(group, "{", "braces", warnings),
+        Task::Repeat(Repeat { tasks, .. })
+        | Task::Forever(Forever { tasks, .. })
+        | Task::When(When { tasks, .. }) => {
+            for task in tasks {
+                lint_task(task, warnings);
+            }
+        }
+        Task::Include(_) | Task::Invocation(_) => {}
+    }
+}
+
+fn lint_process(process: &Process, warnings: &mut Vec<Diagnostic>) {
+    for handler in process.handlers() {
+        match handler {
+            Handler::Process(..) => {}
+            Handler::Concurrent(group, _) => lint_group(group, "[", "brackets", warnings),
+            Handler::Sequential(group) => lint_group(group, "{", "braces", warnings),
+        }
+    }
+}
+
+/// Warns about a block with fewer than two tasks, which could be removed or
+/// replaced by its only task.
+fn lint_group(group: &Group, open: &str, delimiters: &str, warnings: &mut Vec<Diagnostic>) {
+    let warning = match group.tasks.len() {
+        0 => Some((
+            format!("empty `{}` block", open),
+            "this block runs nothing",
+            "remove the block".to_string(),
+        )),
+        1 => Some((
+            format!("`{}` block with a single task", open),
+            "this block only wraps one task",
+            format!("a single task runs on its own without {}", delimiters),
+        )),
+        _ => None,
+    };
+
+    if let Some((message, label, note)) = warning {
+        warnings.push(Diagnostic {
+            severity: Severity::Warning,
+            message,
+            span: group.span,
+            label: Some(label.to_string()),
+            labels: Vec::new(),
+            notes: vec![note],
+        });
+    }
+
+    for task in &group.tasks {
+        lint_task(task, warnings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_job, ParseError, Renderer, Span};
+
+    fn spans(source: &str) -> Result<Vec<(String, Span)>, ParseError> {
+        Ok(lint(&parse_job(source)?)
+            .into_iter()
+            .map(|warning| (warning.message, warning.span))
+            .collect())
+    }
+
+    #[test]
+    fn test_lint_small_blocks() -> Result<(), ParseError> {
+        let source = "[a;] { } [b; c;] d ? { [e;] f; };";
+
+        assert_eq!(
+            spans(source)?,
+            vec![
+                ("`[` block with a single task".to_string(), Span::new(0, 4)),
+                ("empty `{` block".to_string(), Span::new(5, 8)),
+                (
+                    "`[` block with a single task".to_string(),
+                    Span::new(23, 27)
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lint_clean_job() -> Result<(), ParseError> {
+        assert_eq!(spans("[a; b;] { c; d; } e ? f;")?, Vec::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lint_loop_bodies() -> Result<(), ParseError> {
+        assert_eq!(
+            spans("repeat 2 { [a;] } forever { a; }")?,
+            vec![(
+                "`[` block with a single task".to_string(),
+                Span::new(11, 15)
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lint_macro_bodies_once() -> Result<(), ParseError> {
+        let source = "def m() { [a;] } m; b; m;";
+
+        assert_eq!(
+            spans(source)?,
+            vec![(
+                "`[` block with a single task".to_string(),
+                Span::new(10, 14)
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lint_warning() -> Result<(), ParseError> {
+        let warnings = lint(&parse_job("[a;]")?);
+
+        assert_eq!(
+            warnings,
+            vec![Diagnostic {
+                severity: Severity::Warning,
+                message: "`[` block with a single task".to_string(),
+                span: Span::new(0, 4),
+                label: Some("this block only wraps one task".to_string()),
+                labels: Vec::new(),
+                notes: vec!["a single task runs on its own without brackets".to_string()],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_lint_warning() -> Result<(), ParseError> {
+        let source = "build;\n[ test; ]\n";
+        let warnings = lint(&parse_job(source)?);
+
+        let expected = r#"warning: `[` block with a single task
+ --> 2:1
+  |
+2 | [ test; ]
+  | ^^^^^^^^^ this block only wraps one task
+  |
+  = note: a single task runs on its own without brackets"#;
+
+        assert_eq!(Renderer::new().render(&warnings[0], source), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::arpx_job::{Forever, Handler, Job, Process, Repeat, Task, When};
+use crate::error::ParseError;
+use crate::generic::span::Span;
+use crate::line_index::{LineIndex, TextCoordinates};
+use crate::parse_job_with_vars;
+
+/// Reads the job scripts that `load_job` and `include` directives name.
+pub trait SourceLoader {
+    fn load(&self, path: &str) -> io::Result<String>;
+}
+
+/// Reads job scripts from the file system, resolving every path, including
+/// those in `include` directives, relative to a root directory. Paths that
+/// could leave the root, being absolute or going up with `..`, are refused.
+#[derive(Clone, Debug)]
+pub struct FileSystemLoader {
+    root: PathBuf,
+}
+
+impl FileSystemLoader {
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileSystemLoader { root: root.into() }
+    }
+}
+
+impl SourceLoader for FileSystemLoader {
+    fn load(&self, path: &str) -> io::Result<String> {
+        let inside = Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+        if !inside {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{}` is outside the root directory", path),
+            ));
+        }
+
+        fs::read_to_string(self.root.join(path))
+    }
+}
+
+/// Serves job scripts from memory, mostly for tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    #[must_use]
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+
+    #[must_use]
+    pub fn file(mut self, path: impl Into<String>, source: impl Into<String>) -> Self {
+        self.files.insert(path.into(), source.into());
+        self
+    }
+}
+
+impl SourceLoader for MemoryLoader {
+    fn load(&self, path: &str) -> io::Result<String> {
+        self.files.get(path).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no file named `{}`", path))
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadErrorKind {
+    /// The file could not be parsed.
+    Parse(ParseError),
+    /// The file could not be read; holds the reason.
+    Read(String),
+    /// The file includes itself, directly or through other files.
+    Cycle,
+}
+
+/// An `include` directive that led to the file an error is in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncludedFrom {
+    pub path: String,
+    pub span: Span,
+    pub start: TextCoordinates,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadError {
+    pub kind: LoadErrorKind,
+    /// The file the error is in. For `Read` and `Cycle` errors, the file
+    /// could not be used at all, and the first entry of `included_from`
+    /// points at the directive that named it.
+    pub path: String,
+    /// The chain of `include` directives that led to `path`, innermost
+    /// first. Empty if the error is in the file passed to `load_job`.
+    pub included_from: Vec<IncludedFrom>,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            LoadErrorKind::Parse(error) => write!(f, "{} in {}", error, self.path)?,
+            LoadErrorKind::Read(reason) => write!(f, "cannot read {}: {}", self.path, reason)?,
+            LoadErrorKind::Cycle => write!(f, "{} includes itself", self.path)?,
+        }
+
+        for from in &self.included_from {
+            write!(
+                f,
+                "\n  included from {}:{}:{}",
+                from.path,
+                from.start.line,
+                from.start.column + 1
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for LoadError {}
+
+/// Loads the job script at `path` and, recursively, every script it
+/// includes. A script may be included more than once, but not from within
+/// itself. Paths are normalized before they are loaded or compared, so that
+/// `a`, `./a` and `b/../a` name the same script.
+pub fn load_job(path: &str, loader: &dyn SourceLoader) -> Result<Job, LoadError> {
+    load_job_with_vars(path, loader, &HashMap::new())
+}
+
+/// Loads a job script like `load_job`, parsing it and every script it
+/// includes with `parse_job_with_vars`, so that each of them takes the
+/// values of the variables it does not declare from `vars`. Variables
+/// declared with `let` are only visible in the script that declares them,
+/// not in the scripts it includes.
+pub fn load_job_with_vars(
+    path: &str,
+    loader: &dyn SourceLoader,
+    vars: &HashMap<String, String>,
+) -> Result<Job, LoadError> {
+    let mut stack = Vec::new();
+
+    load(path, loader, vars, &mut stack)
+}
+
+/// A file that is being loaded, along with the directive it is currently
+/// resolving, if any.
+struct Frame {
+    path: String,
+    source: String,
+    directive: Option<Span>,
+}
+
+fn load(
+    path: &str,
+    loader: &dyn SourceLoader,
+    vars: &HashMap<String, String>,
+    stack: &mut Vec<Frame>,
+) -> Result<Job, LoadError> {
+    let path = normalize(path);
+    let error = |kind, stack: &[Frame]| LoadError {
+        kind,
+        path: path.clone(),
+        included_from: included_from(stack),
+    };
+
+    if stack.iter().any(|frame| frame.path == path) {
+        return Err(error(LoadErrorKind::Cycle, stack));
+    }
+
+    let source = loader
+        .load(&path)
+        .map_err(|reason| error(LoadErrorKind::Read(reason.to_string()), stack))?;
+    let mut job = parse_job_with_vars(&source, vars)
+        .map_err(|parse_error| error(LoadErrorKind::Parse(parse_error), stack))?;
+
+    stack.push(Frame {
+        path: path.clone(),
+        source,
+        directive: None,
+    });
+
+    let resolved = resolve_tasks(&mut job.tasks, loader, vars, stack);
+    stack.pop();
+
+    resolved.map(|()| job)
+}
+
+/// Drops `.` components and resolves `..` against the component before it,
+/// without touching the file system. A leading `..` is kept, for the loader
+/// to refuse.
+fn normalize(path: &str) -> String {
+    let mut normalized = PathBuf::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized.to_string_lossy().into_owned()
+}
+
+fn resolve_tasks(
+    tasks: &mut [Task],
+    loader: &dyn SourceLoader,
+    vars: &HashMap<String, String>,
+    stack: &mut Vec<Frame>,
+) -> Result<(), LoadError> {
+    for task in tasks {
+        match task {
+            Task::Process(process) => resolve_handlers(process, loader, vars, stack)?,
+            Task::Concurrent(group, _) | Task::Sequential(group) => {
+                resolve_tasks(&mut group.tasks, loader, vars, stack)?;
+            }
+            Task::Invocation(invocation) => {
+                resolve_tasks(&mut invocation.tasks, loader, vars, stack)?;
+            }
+            Task::Repeat(Repeat { tasks, .. })
+            | Task::Forever(Forever { tasks, .. })
+            | Task::When(When { tasks, .. }) => {
+                resolve_tasks(tasks, loader, vars, stack)?;
+            }
+            Task::Include(include) => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.directive = Some(include.span);
+                }
+
+                include.tasks = load(&include.path.value, loader, vars, stack)?.tasks;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_handlers(
+    process: &mut Process,
+    loader: &dyn SourceLoader,
+    vars: &HashMap<String, String>,
+    stack: &mut Vec<Frame>,
+) -> Result<(), LoadError> {
+    for handler in process.handlers_mut() {
+        if let Handler::Concurrent(group, _) | Handler::Sequential(group) = handler {
+            resolve_tasks(&mut group.tasks, loader, vars, stack)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn included_from(stack: &[Frame]) -> Vec<IncludedFrom> {
+    stack
+        .iter()
+        .rev()
+        .filter_map(|frame| {
+            frame.directive.map(|span| IncludedFrom {
+                path: frame.path.clone(),
+                span,
+                start: LineIndex::new(&frame.source).coordinates(span.start),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arpx_job::process_names;
+
+    #[test]
+    fn test_load_job() -> Result<(), LoadError> {
+        let loader = MemoryLoader::new()
+            .file("ci.arpx", "include \"common/setup.arpx\";\nbuild;\n")
+            .file(
+                "common/setup.arpx",
+                "[ db; cache; ] include \"common/env.arpx\";",
+            )
+            .file("common/env.arpx", "env;");
+
+        let job = load_job("ci.arpx", &loader)?;
+
+        assert_eq!(
+            process_names(&job.tasks),
+            vec!["db", "cache", "env", "build"]
+        );
+        assert!(matches!(
+            &job.tasks[0],
+            Task::Include(include) if include.path.value == "common/setup.arpx"
+                && include.tasks[0].span() == Span::new(0, 14)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_job_with_vars() -> Result<(), String> {
+        let loader = MemoryLoader::new()
+            .file("ci.arpx", "let target = \"web\";\ninclude \"deploy\";")
+            .file("deploy", "\"deploy-${env}\";\n\"${target}\";");
+        let vars = HashMap::from([("env".to_string(), "prod".to_string())]);
+
+        let error = load_job_with_vars("ci.arpx", &loader, &vars).unwrap_err();
+
+        let LoadErrorKind::Parse(parse_error) = &error.kind else {
+            return Err(format!("expected a parse error, found {:?}", error.kind));
+        };
+
+        assert_eq!(error.path, "deploy");
+        assert_eq!(parse_error.message(), "`${target}` is not defined");
+
+        let vars = HashMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("target".to_string(), "api".to_string()),
+        ]);
+        let job = load_job_with_vars("ci.arpx", &loader, &vars).map_err(|e| e.to_string())?;
+
+        assert_eq!(process_names(&job.tasks), vec!["deploy-prod", "api"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_twice() -> Result<(), LoadError> {
+        let loader = MemoryLoader::new()
+            .file("ci.arpx", "include \"a\"; { include \"a\"; b; }")
+            .file("a", "a;");
+
+        assert_eq!(
+            process_names(&load_job("ci.arpx", &loader)?.tasks),
+            vec!["a", "a", "b"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let loader = MemoryLoader::new()
+            .file("ci.arpx", "build;\ninclude \"a\";")
+            .file("a", "a ? { include \"b\"; x; };")
+            .file("b", "include \"a\";");
+
+        let error = load_job("ci.arpx", &loader).unwrap_err();
+        let trace: Vec<(&str, Span)> = error
+            .included_from
+            .iter()
+            .map(|from| (from.path.as_str(), from.span))
+            .collect();
+
+        assert_eq!(error.kind, LoadErrorKind::Cycle);
+        assert_eq!(error.path, "a");
+        assert_eq!(
+            trace,
+            vec![
+                ("b", Span::new(0, 12)),
+                ("a", Span::new(6, 18)),
+                ("ci.arpx", Span::new(7, 19)),
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "a includes itself\n  included from b:1:1\n  included from a:1:7\n  included from ci.arpx:2:1"
+        );
+    }
+
+    #[test]
+    fn test_include_cycle_through_equivalent_paths() {
+        let loader = MemoryLoader::new()
+            .file("ci.arpx", "include \"./common/a\";")
+            .file("common/a", "include \"common/../common/a\";");
+
+        let error = load_job("ci.arpx", &loader).unwrap_err();
+
+        assert_eq!(error.kind, LoadErrorKind::Cycle);
+        assert_eq!(error.path, "common/a");
+        assert_eq!(error.included_from.len(), 2);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("./a/./b"), "a/b");
+        assert_eq!(normalize("a//b/../c"), "a/c");
+        assert_eq!(normalize("../a"), "../a");
+        assert_eq!(normalize("a/../../b"), "../b");
+        assert_eq!(normalize("/etc/passwd"), "/etc/passwd");
+    }
+
+    #[test]
+    fn test_parse_error_in_included_file() -> Result<(), String> {
+        let loader = MemoryLoader::new()
+            .file("ci.arpx", "\n  include \"setup\";")
+            .file("setup", "db;\ncache ]");
+
+        let error = load_job("ci.arpx", &loader).unwrap_err();
+
+        let LoadErrorKind::Parse(parse_error) = &error.kind else {
+            return Err(format!("expected a parse error, found {:?}", error.kind));
+        };
+
+        assert_eq!(error.path, "setup");
+        assert_eq!(parse_error.span, Span::new(10, 11));
+        assert_eq!(error.included_from[0].start.line, 2);
+        assert!(error
+            .to_string()
+            .ends_with("in setup\n  included from ci.arpx:2:3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let loader = MemoryLoader::new().file("ci.arpx", "include \"missing\";");
+
+        let error = load_job("ci.arpx", &loader).unwrap_err();
+
+        assert_eq!(
+            error.kind,
+            LoadErrorKind::Read("no file named `missing`".to_string())
+        );
+        assert_eq!(error.included_from[0].span, Span::new(0, 18));
+        assert_eq!(
+            load_job("other.arpx", &loader).unwrap_err().included_from,
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_file_system_loader() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join(format!("arpx-loader-{}", std::process::id()));
+        fs::create_dir_all(root.join("common"))?;
+        fs::write(
+            root.join("ci.arpx"),
+            "include \"common/setup.arpx\"; build;",
+        )?;
+        fs::write(root.join("common/setup.arpx"), "setup;")?;
+
+        let job = load_job("ci.arpx", &FileSystemLoader::new(&root));
+        fs::remove_dir_all(&root)?;
+
+        assert_eq!(process_names(&job?.tasks), vec!["setup", "build"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_system_loader_stays_inside_root() -> Result<(), Box<dyn Error>> {
+        let outside = std::env::temp_dir().join(format!("arpx-outside-{}", std::process::id()));
+        let root = outside.join("root");
+        fs::create_dir_all(&root)?;
+        fs::write(outside.join("secret.arpx"), "secret;")?;
+        fs::write(root.join("up.arpx"), "include \"../secret.arpx\";")?;
+        fs::write(root.join("absolute.arpx"), "include \"/etc/passwd\";")?;
+
+        let loader = FileSystemLoader::new(&root);
+        let up = load_job("up.arpx", &loader);
+        let absolute = load_job("absolute.arpx", &loader);
+        let nested = load_job("sub/../../secret.arpx", &loader);
+        fs::remove_dir_all(&outside)?;
+
+        let up = up.unwrap_err();
+
+        assert_eq!(
+            up.kind,
+            LoadErrorKind::Read("`../secret.arpx` is outside the root directory".to_string())
+        );
+        assert_eq!(
+            absolute.unwrap_err().kind,
+            LoadErrorKind::Read("`/etc/passwd` is outside the root directory".to_string())
+        );
+        assert_eq!(nested.unwrap_err().kind, up.kind);
+        Ok(())
+    }
+}